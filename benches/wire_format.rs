@@ -0,0 +1,108 @@
+//! Throughput of the wire formats a client can ask for, encoding and decoding
+//! the messages of a typical hand. Run with `cargo bench --bench wire_format`.
+use ::poker::{Action, Capability, Card, Event, Frame, Message, Protocol, Suit, PROTOCOL_VERSION};
+use std::time::Instant;
+
+const HANDS: usize = 20_000;
+
+/// The messages a player gets during a hand at a six handed table.
+fn hand() -> Vec<Message> {
+    let card = |rank, suit| Card { rank, suit };
+    let mut messages = vec![
+        Message::Players((0..6).map(|i| Some(format!("player {}", i))).collect()),
+        Message::Hole {
+            player: 0,
+            cards: (card(14, Suit::Spades), card(13, Suit::Spades)),
+        },
+    ];
+    for street in 0..4 {
+        for player in 0..6 {
+            messages.push(Message::PlayerActed {
+                player,
+                action: Action::Call,
+                amount: 2,
+                stack: 200 - 2 * street,
+            });
+        }
+        messages.push(Message::RequestAction {
+            player: 0,
+            bets: vec![Some(2), Some(2), None, Some(2), Some(2), Some(2)],
+            pot: 12 * (street + 1),
+            min_raise: Some(2),
+        });
+        match street {
+            0 => messages.push(Message::Flop(
+                card(2, Suit::Hearts),
+                card(10, Suit::Diamonds),
+                card(7, Suit::Clubs),
+            )),
+            1 => messages.push(Message::Turn(card(11, Suit::Hearts))),
+            2 => messages.push(Message::River(card(4, Suit::Clubs))),
+            _ => {}
+        }
+    }
+    messages
+}
+
+fn main() {
+    let messages = hand();
+    let formats = vec![
+        ("JSON", vec![]),
+        ("JSON, compact cards", vec![Capability::CompactCards]),
+        ("MessagePack", vec![Capability::MessagePack]),
+        (
+            "MessagePack, compact cards",
+            vec![Capability::MessagePack, Capability::CompactCards],
+        ),
+    ];
+    println!(
+        "{:<28}{:>14}{:>20}{:>20}",
+        "format", "bytes/message", "encoded/second", "decoded/second"
+    );
+    for (name, mut capabilities) in formats {
+        capabilities.push(Capability::ActionBroadcasts);
+        let protocol = Protocol::negotiate(PROTOCOL_VERSION, &capabilities).unwrap();
+
+        let start = Instant::now();
+        let mut frames = Vec::with_capacity(HANDS * messages.len());
+        for hand in 0..HANDS {
+            frames.extend(messages.iter().enumerate().filter_map(|(seq, message)| {
+                protocol.encode_event(&Event {
+                    hand,
+                    seq: seq as u64,
+                    message,
+                })
+            }));
+        }
+        let encoding = start.elapsed().as_secs_f64();
+
+        let start = Instant::now();
+        let compact = protocol.has(Capability::CompactCards);
+        let decoded = frames
+            .iter()
+            .filter(|frame| match compact {
+                // compact cards are strings, which only clients read
+                true => frame.decode::<serde_json::Value>().is_some(),
+                false => frame.decode::<Event>().is_some(),
+            })
+            .count();
+        let decoding = start.elapsed().as_secs_f64();
+        assert_eq!(decoded, frames.len());
+
+        let bytes = frames
+            .iter()
+            .map(|frame| match frame {
+                Frame::Text(text) => text.len(),
+                Frame::Binary(bytes) => bytes.len(),
+            })
+            .sum::<usize>();
+        let n = frames.len() as f64;
+        println!(
+            "{:<28}{:>14.1}{:>20.0}{:>20.0}",
+            name,
+            bytes as f64 / n,
+            n / encoding,
+            n / decoding
+        );
+    }
+}
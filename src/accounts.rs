@@ -0,0 +1,132 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use crate::communication::LoginError;
+
+/// The players that may log in, by name with their pre-shared token.
+#[derive(Clone, Default)]
+pub struct Accounts {
+    // None lets anybody log in under any name that is not taken
+    tokens: Option<HashMap<String, String>>,
+    // the names that are logged in right now
+    online: Arc<Mutex<HashSet<String>>>,
+    // the most players that can be logged in at once
+    max_online: Option<usize>,
+}
+
+impl Accounts {
+    /// Accounts for a server without a list of players, where nobody needs a
+    /// token.
+    pub fn open() -> Accounts {
+        Accounts::default()
+    }
+
+    /// Read the accounts from a JSON file that maps names to tokens.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Accounts> {
+        let tokens = serde_json::from_str(&fs::read_to_string(path)?)?;
+        Ok(Accounts {
+            tokens: Some(tokens),
+            ..Accounts::default()
+        })
+    }
+
+    /// Turn players away once `max_online` of them are logged in.
+    pub fn limit(self, max_online: Option<usize>) -> Accounts {
+        Accounts { max_online, ..self }
+    }
+
+    /// Log a player in. The name stays taken until the session is dropped.
+    pub fn login(&self, name: &str, token: &str) -> Result<Session, LoginError> {
+        if name.is_empty() {
+            return Err(LoginError::InvalidCredentials);
+        }
+        if let Some(tokens) = &self.tokens {
+            if tokens.get(name).is_none_or(|t| t != token) {
+                return Err(LoginError::InvalidCredentials);
+            }
+        }
+        let mut online = self.online.lock().unwrap();
+        if online.contains(name) {
+            return Err(LoginError::AlreadyLoggedIn);
+        }
+        if self.max_online.is_some_and(|max| online.len() >= max) {
+            return Err(LoginError::ServerFull);
+        }
+        online.insert(name.to_string());
+        Ok(Session {
+            name: name.to_string(),
+            online: self.online.clone(),
+        })
+    }
+}
+
+/// A logged in player. Dropping the session logs the player out.
+pub struct Session {
+    name: String,
+    online: Arc<Mutex<HashSet<String>>>,
+}
+
+impl Session {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        self.online.lock().unwrap().remove(&self.name);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_login() {
+        let accounts = Accounts {
+            tokens: Some(
+                vec![("alice", "a"), ("bob", "b")]
+                    .into_iter()
+                    .map(|(name, token)| (name.to_string(), token.to_string()))
+                    .collect(),
+            ),
+            ..Accounts::default()
+        };
+        let alice = accounts.login("alice", "a").unwrap();
+        assert_eq!(alice.name(), "alice");
+        assert_eq!(
+            accounts.login("alice", "a").err(),
+            Some(LoginError::AlreadyLoggedIn)
+        );
+        assert_eq!(
+            accounts.login("bob", "a").err(),
+            Some(LoginError::InvalidCredentials)
+        );
+        assert_eq!(
+            accounts.login("carol", "c").err(),
+            Some(LoginError::InvalidCredentials)
+        );
+        drop(alice);
+        assert!(accounts.login("alice", "a").is_ok());
+
+        // without a list of players only the names are checked
+        let open = Accounts::open();
+        let carol = open.login("carol", "").unwrap();
+        assert_eq!(
+            open.login("carol", "x").err(),
+            Some(LoginError::AlreadyLoggedIn)
+        );
+        assert!(open.login("", "").is_err());
+        drop(carol);
+
+        let full = Accounts::open().limit(Some(1));
+        let dave = full.login("dave", "").unwrap();
+        assert_eq!(full.login("erin", "").err(), Some(LoginError::ServerFull));
+        drop(dave);
+        assert!(full.login("erin", "").is_ok());
+    }
+}
@@ -1,80 +1,258 @@
+use std::io::{self, ErrorKind};
+use std::panic::{self, AssertUnwindSafe};
 use std::thread;
-use std::time::Duration;
-use websocket::sync::{Client, Server, Stream};
-use websocket::{Message as WsMessage, OwnedMessage};
+use std::time::{Duration, Instant};
+use websocket::sync::Server;
 
+use crate::accounts::{Accounts, Session};
+use crate::cash::CashTable;
 use crate::communication::{
-    Callback, Error, ErrorMessage, Message, RequestTable, Response, TableRequest,
+    BuyIn, Callback, Card, Error, ErrorMessage, Event, Format, Login, LoginError, LoginReply,
+    Message, Money, PlayerAction, RequestTable, Response, Seating, StateSnapshot, TableRequest,
+    TableStatus,
 };
+use crate::config::Config;
 use crate::engine::Table;
+use crate::ledger::{Ledger, Record};
+use crate::lobby::{TableHandle, Tables, Watcher};
+use crate::protocol::{Capability, Frame, Protocol};
+use crate::state::GameState;
+use crate::status::serve_status;
+use crate::tournament::MultiTable;
+use crate::transport::{Lines, Transport};
+use serde::Serialize;
 use std::collections::HashMap;
-use std::sync::mpsc::{channel, Sender};
+use std::net::TcpListener;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, SendError, Sender};
+use std::sync::{Arc, Mutex};
+use websocket::server::InvalidConnection;
 
 /* TODO
 * switch to async for the table-request code
 **/
 
-/// runs indefinitely. starts a child thread that listens for new connections.
+// how often the server looks for new connections, and whether the tables
+// stopped when shutting down
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Stops a running server: it stops accepting clients, lets every table
+/// finish the current round and tell its players their final stacks, and
+/// sends everybody away before `run_server` returns.
+#[derive(Clone, Default)]
+pub struct Shutdown(Arc<AtomicBool>);
+
+impl Shutdown {
+    pub fn new() -> Shutdown {
+        Shutdown::default()
+    }
+
+    pub fn shut_down(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_shut_down(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// runs until `shutdown`. starts a child thread that listens for new connections.
 /// once enough players have been collected, starts a new game in a separate
 /// thread. Each game sends all clients back to the main thread at the end of
-/// the game.
-pub fn run_server(address: &str) {
+/// the game. Cash tables are different: they start with the first player and
+/// keep running, players that leave them go back to the lobby. Clients in the
+/// lobby get a listing of all forming and running tables whenever it changes,
+/// and can watch running tables as spectators. The predefined tables of the
+/// `config` are always in the listing, other tables can be asked for within
+/// its limits. If the config asks for it, a status page is served as well.
+/// Clients have to log in with one of the `accounts` first, and the chips
+/// they win and lose are kept in the `ledger`.
+/// Returns: an error if the server could not listen on its addresses
+pub fn run_server(
+    config: Config,
+    accounts: Accounts,
+    ledger: Ledger,
+    shutdown: Shutdown,
+) -> io::Result<()> {
     let (tx, rx) = channel();
+    let ledger = Arc::new(Mutex::new(ledger));
+    let leaderboard = ledger.clone();
+
+    // listen on every address before anything starts running
+    let mut server = Server::bind(config.address())?;
+    server.set_nonblocking(true)?;
+    let status_listener = match config.status_address() {
+        Some(status_address) => Some(TcpListener::bind(status_address)?),
+        None => None,
+    };
+    let tcp_listener = match config.tcp_address() {
+        Some(tcp_address) => {
+            let listener = TcpListener::bind(tcp_address)?;
+            listener.set_nonblocking(true)?;
+            Some(listener)
+        }
+        None => None,
+    };
 
     // set up server in separate thread to accept new clients and send them to dispatcher
-    let server = Server::bind(address).unwrap();
     let tx2 = tx.clone();
     let (incoming_tx, incoming_rx) = channel();
+    let lobby_tx = incoming_tx.clone();
+    let spectator_tx = incoming_tx.clone();
+    let gate = Gate {
+        accounts: accounts.limit(config.max_connections),
+        lobby: incoming_tx,
+        login_timeout: Duration::from_secs(config.timeouts.login),
+        action_timeout: config.timeouts.action.map(Duration::from_secs),
+    };
+    let tables = Arc::new(Mutex::new(Tables::new()));
+    let listing = tables.clone();
 
-    let (update_tx, update_rx) = channel();
+    if let Some(listener) = status_listener {
+        let tables = tables.clone();
+        let ledger = ledger.clone();
+        thread::spawn(move || serve_status(listener, tables, ledger));
+    }
 
-    // connection-accepting thread
-    thread::spawn(move || {
-        for client in server
-            .filter_map(Result::ok)
-            .filter_map(|connection| connection.accept().ok())
-        {
-            println!("accepting a connection from {:?}", client.peer_addr());
-            incoming_tx
-                .send(client)
-                .expect("incoming-rx thread hung up");
+    // the bots the server runs itself log in like everybody else
+    for command in &config.bots {
+        match Lines::spawn(command) {
+            Ok(bot) => gate.admit(Box::new(bot)),
+            Err(e) => log!("could not run bot {:?}: {}", command, e),
         }
-    });
+    }
+
+    // connection-accepting threads, every new client logs in on a thread of
+    // its own before going to the lobby
+    let mut acceptors = Vec::new();
+    if let Some(listener) = tcp_listener {
+        let accepting = shutdown.clone();
+        let gate = gate.clone();
+        acceptors.push(thread::spawn(move || {
+            while !accepting.is_shut_down() {
+                match listener.accept() {
+                    Ok((stream, address)) => {
+                        log!("accepting a TCP connection from {}", address);
+                        match stream
+                            .set_nonblocking(false)
+                            .and_then(|_| Lines::tcp(stream))
+                        {
+                            Ok(lines) => gate.admit(Box::new(lines)),
+                            Err(e) => log!("could not set up the connection: {}", e),
+                        }
+                    }
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => thread::sleep(POLL_INTERVAL),
+                    Err(_) => continue,
+                }
+            }
+        }));
+    }
+    let accepting = shutdown.clone();
+    acceptors.push(thread::spawn(move || {
+        while !accepting.is_shut_down() {
+            let client = match server.accept() {
+                Ok(connection) => match connection.accept() {
+                    Ok(client) => client,
+                    Err(_) => continue,
+                },
+                // nobody is connecting
+                Err(InvalidConnection { stream: None, .. }) => {
+                    thread::sleep(POLL_INTERVAL);
+                    continue;
+                }
+                Err(_) => continue,
+            };
+            log!("accepting a connection from {:?}", client.peer_addr());
+            gate.admit(Box::new(client));
+        }
+    }));
 
     // give-updates-and-listen-for-table-type thread
-    thread::spawn(move || {
+    let allowed = config.clone();
+    let stopping = shutdown.clone();
+    let lobby = thread::spawn(move || {
         let mut clients = Vec::new();
+        let mut last_update = String::new();
         loop {
-            // if we receive a new client within 1 second, add them to the main queue
-            if let Ok(client) = incoming_rx.recv_timeout(Duration::from_secs(1)) {
-                client.set_nonblocking(true).unwrap();
-                clients.push(client);
+            // when shutting down, send everybody away, including the players
+            // that come back from the tables, until all tables stopped
+            if stopping.is_shut_down() {
+                let stopped = listing.lock().unwrap().is_empty();
+                for mut client in clients.drain(..).chain(incoming_rx.try_iter()) {
+                    client.close();
+                }
+                if stopped {
+                    break;
+                }
+                thread::sleep(POLL_INTERVAL);
+                continue;
+            }
+
+            let lobby = listing.lock().unwrap().lobby();
+            let update = serde_json::to_string(&lobby).unwrap();
+
+            // if we receive a new client within 1 second, add them to the main
+            // queue and tell them which tables there are, unless that is
+            // broadcast anyway
+            if let Ok(mut client) = incoming_rx.recv_timeout(Duration::from_secs(1)) {
+                // a client whose connection already broke is dropped
+                if client.set_nonblocking(true).is_err() {
+                    continue;
+                }
+                if update != last_update || client.send_value(&lobby).is_ok() {
+                    clients.push(client);
+                }
             }
 
-            // If we receive an update, broadcast amongst all queued clients.
+            // If the tables changed, broadcast amongst all queued clients.
             // Drop clients whose connection fails.
-            if let Ok(update) = update_rx.recv_timeout(Duration::from_secs(1)) {
+            if update != last_update {
                 for i in (0..clients.len()).rev() {
-                    if clients[i]
-                        .send_message(&WsMessage::text(serde_json::to_string(&update).unwrap()))
-                        .is_err()
-                    {
+                    if clients[i].send_value(&lobby).is_err() {
                         clients.remove(i);
                     }
                 }
+                last_update = update;
             }
+            listing.lock().unwrap().set_lobby_clients(clients.len());
 
             // If any of the clients has decided on a table, send them to the tables queue. If we don't understand the message, drop the connection.
             for i in (0..clients.len()).rev() {
-                if let Ok(OwnedMessage::Text(msg)) = clients[i].recv_message() {
+                if let Ok(frame) = clients[i].recv() {
                     let mut client = clients.remove(i);
-                    if let Ok(RequestTable::Table(request)) =
-                        serde_json::from_str::<RequestTable>(&msg)
-                    {
+                    let request = match frame.decode::<RequestTable>() {
+                        Some(RequestTable::Table(request)) if allowed.allows(&request) => {
+                            Some(request)
+                        }
+                        Some(RequestTable::Join { table, buy_in }) => {
+                            join_request(&listing.lock().unwrap(), table, buy_in)
+                        }
+                        Some(RequestTable::Leaderboard) => {
+                            let board = leaderboard.lock().unwrap().leaderboard();
+                            if client.send_value(&board).is_ok() {
+                                clients.push(client);
+                            }
+                            continue;
+                        }
+                        Some(RequestTable::Watch { table, delay }) => {
+                            let (watcher, messages) = channel();
+                            if listing.lock().unwrap().watch(table, watcher) {
+                                client.set_nonblocking(false).ok();
+                                let lobby = spectator_tx.clone();
+                                thread::spawn(move || {
+                                    spectate(client, Duration::from_secs(delay), messages, lobby);
+                                });
+                                continue;
+                            }
+                            None
+                        }
+                        _ => None,
+                    };
+                    if let Some(request) = request {
                         client.set_nonblocking(false).ok();
-                        tx2.send((request, client)).expect("main thread hung up");
+                        tx2.send((request, client)).ok();
                     } else {
-                        client.send_message(&WsMessage::close()).ok();
+                        client.close();
                     }
                 }
             }
@@ -82,131 +260,1428 @@ pub fn run_server(address: &str) {
     });
 
     // listen to clients from server and from stopped games
+    let open_forming = |table: &TableRequest| {
+        let id = tables
+            .lock()
+            .unwrap()
+            .open(table.clone(), TableStatus::Forming);
+        (id, Vec::new())
+    };
     let mut queue = HashMap::new();
-    while let Ok((table, client)) = rx.recv() {
-        {
-            let q = queue.entry(table).or_insert_with(Vec::new);
+    // running cash tables by request regardless of the stack, with their id
+    // and the channel to seat players at them
+    let mut cash_tables: HashMap<TableRequest, (usize, Sender<_>)> = HashMap::new();
+    for table in &config.tables {
+        if let Format::CashTable(buy_in) = table.format {
+            let table = TableRequest {
+                stack: 0,
+                ..table.clone()
+            };
+            let (id, seats) = start_cash_table(&table, buy_in, &tables, &ledger, &lobby_tx);
+            cash_tables.insert(table, (id, seats));
+        } else {
+            queue.insert(table.clone(), open_forming(table));
+        }
+    }
+    while !shutdown.is_shut_down() {
+        let (table, mut client) = match rx.recv_timeout(Duration::from_secs(1)) {
+            Ok(arrival) => arrival,
+            Err(_) => continue,
+        };
+        if let Format::CashTable(buy_in) = table.format {
+            let stack = table.stack;
+            if stack < buy_in.min || stack > buy_in.max {
+                client.close();
+                continue;
+            }
+            let table = TableRequest { stack: 0, ..table };
+            let arrival = match cash_tables.get(&table) {
+                Some((_, seats)) => seats
+                    .send((client, stack))
+                    .err()
+                    .map(|SendError(arrival)| arrival),
+                None => Some((client, stack)),
+            };
+            // open a new table if there is none or it stopped
+            if let Some(arrival) = arrival {
+                let (id, seats) = start_cash_table(&table, buy_in, &tables, &ledger, &lobby_tx);
+                seats.send(arrival).ok();
+                cash_tables.insert(table, (id, seats));
+            }
+        } else {
+            let (id, q) = queue
+                .entry(table.clone())
+                .or_insert_with(|| open_forming(&table));
             q.push(client);
+            tables.lock().unwrap().set_seated(*id, q.len());
             if q.len() == table.n_players {
-                // play game with n_players.
-                let tx3 = tx.clone();
-                let mut clients = Vec::new();
-                for _ in 0..table.n_players {
-                    clients.push(q.pop().unwrap());
+                // play game with n_players. The next player asking for this
+                // table starts forming a new one
+                let (id, clients) = queue.remove(&table).unwrap();
+                tables.lock().unwrap().start(id);
+                if config.tables.contains(&table) {
+                    queue.insert(table.clone(), open_forming(&table));
                 }
+                let tx3 = tx.clone();
+                let lobby = lobby_tx.clone();
+                let handle = TableHandle {
+                    id,
+                    tables: tables.clone(),
+                };
+                let ledger = ledger.clone();
                 thread::spawn(move || {
-                    do_game(table, clients, tx3);
+                    do_game(table, clients, tx3, lobby, handle.clone(), ledger);
+                    handle.close();
                 });
             }
         }
-        // send update about queues
-        update_tx
-            .send(queue.iter().map(|(&k, v)| (k, v.len())).collect::<Vec<_>>())
+    }
+
+    // let the running tables finish their round, and send away the players
+    // waiting for a table or coming back from one
+    log!("shutting down, waiting for the tables to stop");
+    tables.lock().unwrap().shut_down();
+    drop(cash_tables);
+    for (_, (id, clients)) in queue.drain() {
+        tables.lock().unwrap().close(id);
+        for mut client in clients {
+            client.close();
+        }
+    }
+    loop {
+        let stopped = tables.lock().unwrap().is_empty();
+        for (_, mut client) in rx.try_iter() {
+            client.close();
+        }
+        if stopped {
+            break;
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+    for acceptor in acceptors {
+        acceptor.join().ok();
+    }
+    lobby.join().ok();
+
+    if let Err(e) = ledger.lock().unwrap().sync() {
+        log!("could not write the ledger: {}", e);
+    }
+    log!("server stopped");
+    crate::log::flush();
+    Ok(())
+}
+
+/// Start a cash table in the lobby listing, without players.
+/// Returns: the id of the table and the channel to seat players at it
+fn start_cash_table(
+    table: &TableRequest,
+    buy_in: BuyIn,
+    tables: &Arc<Mutex<Tables>>,
+    ledger: &Arc<Mutex<Ledger>>,
+    lobby: &Sender<Player>,
+) -> (usize, Sender<(Player, Money)>) {
+    let (seats_tx, seats_rx) = channel();
+    let id = tables
+        .lock()
+        .unwrap()
+        .open(table.clone(), TableStatus::Running);
+    let handle = TableHandle {
+        id,
+        tables: tables.clone(),
+    };
+    let table = table.clone();
+    let ledger = ledger.clone();
+    let lobby = lobby.clone();
+    thread::spawn(move || {
+        do_cash_table(handle.clone(), ledger, table, buy_in, seats_rx, lobby);
+        handle.close();
+    });
+    (id, seats_tx)
+}
+
+/// Where new connections go: they log in on a thread of their own, and go to
+/// the lobby once they did.
+#[derive(Clone)]
+struct Gate {
+    accounts: Accounts,
+    lobby: Sender<Player>,
+    login_timeout: Duration,
+    action_timeout: Option<Duration>,
+}
+
+impl Gate {
+    /// Let a new connection log in, without waiting for it.
+    fn admit(&self, connection: Box<dyn Transport>) {
+        let gate = self.clone();
+        thread::spawn(move || {
+            if let Some(player) = gate.login(connection) {
+                log!("{} logged in", player.session.name());
+                gate.lobby.send(player).ok();
+            }
+        });
+    }
+
+    /// Wait for a new client to log in and tell them whether that worked.
+    /// Once logged in, the client gets the action timeout for every
+    /// response.
+    /// Returns: the player, or None if the client was rejected
+    fn login(&self, mut connection: Box<dyn Transport>) -> Option<Player> {
+        connection.set_read_timeout(Some(self.login_timeout)).ok();
+        let login = match connection
+            .recv()
+            .ok()
+            .and_then(|frame| frame.decode::<Login>())
+        {
+            Some(mut login) => {
+                // binary messages only fit in websocket frames
+                if !connection.binary() {
+                    login.capabilities.retain(|&c| c != Capability::MessagePack);
+                }
+                Protocol::negotiate(login.version, &login.capabilities).and_then(|protocol| {
+                    Ok((self.accounts.login(&login.name, &login.token)?, protocol))
+                })
+            }
+            None => Err(LoginError::InvalidLogin),
+        };
+        connection.set_read_timeout(self.action_timeout).ok();
+
+        let reply = match &login {
+            Ok((_, protocol)) if protocol.version == 1 => LoginReply::Welcome,
+            Ok((_, protocol)) => LoginReply::Accepted(protocol.clone()),
+            Err(error) => LoginReply::Rejected(*error),
+        };
+        connection
+            .send(Frame::Text(serde_json::to_string(&reply).unwrap()))
             .ok();
+        match login {
+            Ok((session, protocol)) => Some(Player {
+                connection,
+                session,
+                protocol,
+            }),
+            Err(_) => {
+                connection.close();
+                None
+            }
+        }
     }
 }
 
+/// The request to send a player to when they ask to join a table from the
+/// listing: forming tables can be joined with their own request, running
+/// tables only if they are cash tables.
+fn join_request(tables: &Tables, id: usize, buy_in: Option<Money>) -> Option<TableRequest> {
+    match tables.get(id)? {
+        (request, TableStatus::Forming) => Some(request.clone()),
+        (request, TableStatus::Running) => match request.format {
+            Format::CashTable(limits) => Some(TableRequest {
+                stack: buy_in.unwrap_or(limits.min),
+                ..request.clone()
+            }),
+            _ => None,
+        },
+    }
+}
+
+/// Forward the messages of a table to a spectator, each message `delay` after
+/// it was sent, in the form the spectator asked for. The spectator goes back
+/// to the lobby once the table stops.
+fn spectate(
+    mut client: Player,
+    delay: Duration,
+    messages: Receiver<(Instant, Event)>,
+    lobby: Sender<Player>,
+) {
+    for (sent, event) in messages {
+        if let Some(wait) = (sent + delay).checked_duration_since(Instant::now()) {
+            thread::sleep(wait);
+        }
+        let event = Event {
+            hand: event.hand,
+            seq: event.seq,
+            message: &event.message,
+        };
+        if client.send(&event).is_err() {
+            return;
+        }
+    }
+    lobby.send(client).ok();
+}
+
 /// Single-game-type logic. Create a table and keep playing until one of the
-/// players generates an error. Kick that player and return the other players
-/// to the queue.
-fn do_game<S>(
+/// players generates an error or the tournament is over. Kick the offending
+/// player and return the other players to the queue. When the table panics,
+/// its players go back to the lobby instead.
+fn do_game(
     // game_type: GameType,
     // small_blind: Money,
     // big_blind: Money,
     // stack: Money,
     table_request: TableRequest,
-    mut clients: Vec<Client<S>>,
-    tx3: Sender<(TableRequest, Client<S>)>,
-) where
-    S: Stream + Send + 'static,
-{
-    Table::new(
+    clients: Vec<Player>,
+    tx3: Sender<(TableRequest, Player)>,
+    lobby: Sender<Player>,
+    handle: TableHandle,
+    ledger: Arc<Mutex<Ledger>>,
+) {
+    let id = handle.id;
+    if let Format::MultiTable {
+        schedule,
+        table_size,
+    } = &table_request.format
+    {
+        // tables need at least two seats to play
+        let table_size = (*table_size).max(2);
+        let n_tables = clients.len().div_ceil(table_size);
+        let mut seats = (0..n_tables)
+            .map(|_| (0..table_size).map(|_| None).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        // the tables only see part of the tournament, its result is
+        // recorded here
+        let adapters = seats
+            .iter_mut()
+            .map(|clients| {
+                let mut adapter = Adapter::new(
+                    clients,
+                    handle.clone(),
+                    ledger.clone(),
+                    table_request.big_blind,
+                );
+                adapter.records_games = false;
+                adapter
+            })
+            .collect();
+        let names = clients
+            .iter()
+            .map(|client| client.session.name().to_string())
+            .collect::<Vec<_>>();
+        let played = panic::catch_unwind(AssertUnwindSafe(|| {
+            MultiTable::new(&table_request, schedule, table_size, adapters, clients).play(
+                |client| {
+                    tx3.send((table_request.clone(), client)).ok();
+                },
+            )
+        }));
+        match played {
+            Ok(Some(standings)) => {
+                log!("tournament finished, standings: {:?}", standings);
+                ledger.lock().unwrap().record(Record::Game {
+                    table: id,
+                    standings: standings.iter().map(|&e| names[e].clone()).collect(),
+                });
+            }
+            Ok(None) => {}
+            Err(_) => log!("table {} panicked", id),
+        }
+        // players are only left in their seats when the tournament is aborted
+        for client in seats.into_iter().flatten().flatten() {
+            lobby.send(client).ok();
+        }
+        return;
+    }
+
+    let n_players = clients.len();
+    let mut seats = (0..n_players).map(|_| None).collect();
+    let mut adapter = Adapter::new(&mut seats, handle, ledger, table_request.big_blind);
+    adapter.state = GameState::new(vec![table_request.stack; n_players]);
+    for (seat, client) in clients.into_iter().enumerate() {
+        adapter.join(seat, client, table_request.stack);
+    }
+    let table = Table::new(
         table_request.game_type,
         table_request.small_blind,
         table_request.big_blind,
-        vec![table_request.stack; clients.len()],
-        Adapter {
-            clients: &mut clients,
-        },
-    )
-    .play();
-    // one of the players got kicked for erroring, return other players
-    while let Some(client) = clients.pop() {
-        tx3.send((table_request, client)).ok();
+        table_request.ante,
+        table_request.straddle,
+        vec![table_request.stack; n_players],
+        adapter,
+    );
+    let played = panic::catch_unwind(AssertUnwindSafe(|| match &table_request.format {
+        Format::Tournament(schedule) => table.play_tournament(schedule),
+        _ => table.play(),
+    }));
+    if !matches!(played, Ok(true)) {
+        if played.is_err() {
+            log!("table {} panicked", id);
+        }
+        for client in seats.into_iter().flatten() {
+            lobby.send(client).ok();
+        }
+        return;
+    }
+    // the game is over or one of the players got kicked for erroring, return
+    // other players
+    while let Some(client) = seats.pop() {
+        if let Some(client) = client {
+            tx3.send((table_request.clone(), client)).ok();
+        }
     }
 }
 
-/// Adapter adapts websocket messages to game messages. In addition the adapter
+/// Run a cash table for as long as the server runs. Players that leave the
+/// table go back to the lobby, as do the players still seated when it panics.
+fn do_cash_table(
+    handle: TableHandle,
+    ledger: Arc<Mutex<Ledger>>,
+    table_request: TableRequest,
+    buy_in: BuyIn,
+    arrivals: Receiver<(Player, Money)>,
+    lobby: Sender<Player>,
+) {
+    let mut clients = (0..table_request.n_players).map(|_| None).collect();
+    let id = handle.id;
+    let mut adapter = Adapter::new(&mut clients, handle, ledger, table_request.big_blind);
+    adapter.cash = true;
+    let played = panic::catch_unwind(AssertUnwindSafe(|| {
+        CashTable::new(id, &table_request, buy_in, adapter).play(&arrivals, |client| {
+            lobby.send(client).ok();
+        })
+    }));
+    if played.is_err() {
+        log!("table {} panicked", id);
+    }
+    for client in clients.into_iter().flatten() {
+        lobby.send(client).ok();
+    }
+}
+
+/// A client that logged in. Dropping it logs the player out.
+struct Player {
+    connection: Box<dyn Transport>,
+    session: Session,
+    protocol: Protocol,
+}
+
+impl Player {
+    /// Send a table message in the form the client asked for, if it wants
+    /// the message at all.
+    fn send(&mut self, event: &Event<&Message>) -> io::Result<()> {
+        match self.protocol.encode_event(event) {
+            Some(frame) => self.connection.send(frame),
+            None => Ok(()),
+        }
+    }
+
+    /// Send any other message in the form the client asked for.
+    fn send_value<T: Serialize>(&mut self, message: &T) -> io::Result<()> {
+        let frame = self.protocol.encode(message);
+        self.connection.send(frame)
+    }
+
+    fn recv(&mut self) -> io::Result<Frame> {
+        self.connection.recv()
+    }
+
+    fn set_nonblocking(&mut self, nonblocking: bool) -> io::Result<()> {
+        self.connection.set_nonblocking(nonblocking)
+    }
+
+    fn close(&mut self) {
+        self.connection.close();
+    }
+}
+
+/// Adapter adapts client messages to game messages. In addition the adapter
 /// manages communication, so the adapter receives all updates from the game
-/// and decides how to dispatch them to the clients.
-struct Adapter<'a, S>
-where
-    S: Stream + Send,
-{
-    clients: &'a mut Vec<Client<S>>,
-}
-
-impl<'a, S> Callback for Adapter<'a, S>
-where
-    S: Stream + Send + 'static,
-{
-    fn callback(&mut self, message: Message) -> Result<Response, Error> {
-        match message {
-            Message::RequestAction { player, .. } => {
-                self.clients[player]
-                    .send_message(&WsMessage::text(serde_json::to_string(&message).unwrap()))
-                    .or(Err(Error {
-                        player,
-                        error: ErrorMessage::WebSocketError,
-                    }))?;
-                self.clients[player]
-                    .recv_message()
-                    .or(Err(Error {
-                        player,
-                        error: ErrorMessage::WebSocketError,
-                    }))
-                    .and_then(|m| {
-                        if let OwnedMessage::Text(t) = m {
-                            serde_json::from_str::<Response>(&t).or(Err(Error {
-                                player,
-                                error: ErrorMessage::InvalidResponse,
-                            }))
-                        } else {
-                            Err(Error {
-                                player,
-                                error: ErrorMessage::InvalidResponse,
-                            })
-                        }
-                    })
+/// and decides how to dispatch them to the clients, including spectators.
+struct Adapter<'a> {
+    // the player in every seat of the table, None for empty seats
+    clients: &'a mut Vec<Option<Player>>,
+    // requests players sent while they were asked to act, by seat
+    requests: Vec<(usize, Response)>,
+    // whether players may stand up and top up between rounds, which only
+    // cash tables allow
+    cash: bool,
+    // the table in the lobby listing, updated as players come and go and
+    // hands are played
+    table: TableHandle,
+    new_watchers: Receiver<Watcher>,
+    watchers: Vec<Watcher>,
+    // the hole cards of every seat that has not folded this hand, only shown
+    // to spectators at the showdown
+    holes: Vec<Option<(Card, Card)>>,
+    ledger: Arc<Mutex<Ledger>>,
+    big_blind: Money,
+    // the stack every seat started the current hand with, None for seats
+    // that have not acted yet
+    hand: Vec<Option<Money>>,
+    // whether finished games are written to the ledger, with the players
+    // that busted so far in the order they busted
+    records_games: bool,
+    busted: Vec<String>,
+    // the state of the table, to send snapshots of
+    state: GameState,
+    // the sequence number of the last message of the table
+    seq: u64,
+}
+
+impl<'a> Adapter<'a> {
+    fn new(
+        clients: &'a mut Vec<Option<Player>>,
+        table: TableHandle,
+        ledger: Arc<Mutex<Ledger>>,
+        big_blind: Money,
+    ) -> Adapter<'a> {
+        let holes = vec![None; clients.len()];
+        let hand = vec![None; clients.len()];
+        let state = GameState::new(vec![0; clients.len()]);
+        Adapter {
+            clients,
+            requests: Vec::new(),
+            cash: false,
+            new_watchers: table.watchers(),
+            table,
+            watchers: Vec::new(),
+            holes,
+            ledger,
+            big_blind,
+            hand,
+            records_games: true,
+            busted: Vec::new(),
+            state,
+            seq: 0,
+        }
+    }
+
+    /// Empty a seat and take its connection. Seats are never removed, so the
+    /// other players keep the seat the table knows them by.
+    fn unseat(&mut self, seat: usize) -> Option<Player> {
+        // players only leave during a hand if it is undone
+        self.hand.iter_mut().for_each(|start| *start = None);
+        let client = self.clients[seat].take();
+        self.holes[seat] = None;
+        if client.is_some() {
+            self.table.left();
+            self.broadcast_names();
+        }
+        client
+    }
+
+    /// The name of the player in the given seat.
+    fn name(&self, seat: usize) -> Option<String> {
+        Some(self.clients[seat].as_ref()?.session.name().to_string())
+    }
+
+    /// Write down what every player that took part in the hand won or lost.
+    fn record_hand(&mut self, stacks: &[Money]) {
+        // players that bust in the same hand are ranked by their stack
+        let mut busted = (0..self.hand.len())
+            .filter_map(|seat| match self.hand[seat] {
+                Some(start) if stacks[seat] == 0 => Some((start, self.name(seat)?)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        busted.sort();
+        self.busted.extend(busted.into_iter().map(|(_, name)| name));
+
+        let results = (0..self.hand.len())
+            .filter_map(|seat| {
+                let start = self.hand[seat].take()?;
+                Some((self.name(seat)?, stacks[seat] as i64 - start as i64))
+            })
+            .collect::<Vec<_>>();
+        if !results.is_empty() {
+            self.ledger.lock().unwrap().record(Record::Hand {
+                table: self.table.id,
+                big_blind: self.big_blind,
+                results,
+            });
+        }
+    }
+
+    /// Write down the ranking of a finished game.
+    /// standings: the seats of the players that did not bust, the winner first
+    fn record_game(&mut self, standings: &[usize]) {
+        let mut standings = standings
+            .iter()
+            .filter_map(|&seat| self.name(seat))
+            .filter(|name| !self.busted.contains(name))
+            .collect::<Vec<_>>();
+        standings.extend(self.busted.drain(..).rev());
+        if self.records_games && standings.len() > 1 {
+            self.ledger.lock().unwrap().record(Record::Game {
+                table: self.table.id,
+                standings,
+            });
+        }
+    }
+
+    /// Send a public message to the spectators, dropping those that left.
+    fn spectate(&mut self, event: &Event<&Message>) {
+        self.watchers.extend(self.new_watchers.try_iter());
+        if self.watchers.is_empty() {
+            return;
+        }
+        let sent = Instant::now();
+        let event = Event {
+            hand: event.hand,
+            seq: event.seq,
+            message: event.message.clone(),
+        };
+        self.watchers
+            .retain(|watcher| watcher.send((sent, event.clone())).is_ok());
+    }
+
+    /// Show the spectators the hole cards of the players that are still in
+    /// the hand, if there is more than one.
+    fn show_holes(&mut self) {
+        let shown = self
+            .holes
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(player, cards)| Some((player, cards.take()?)))
+            .collect::<Vec<_>>();
+        if shown.len() > 1 {
+            for (player, cards) in shown {
+                let message = Message::Hole { player, cards };
+                let event = self.event(&message);
+                self.spectate(&event);
             }
-            Message::Hole { player, .. } => self.clients[player]
-                .send_message(&WsMessage::text(serde_json::to_string(&message).unwrap()))
-                .and(Ok(Response::Ack))
+        }
+    }
+
+    /// The client in the given seat, which should not be empty.
+    fn client(&mut self, player: usize) -> Result<&mut Player, Error> {
+        self.clients[player].as_mut().ok_or(Error {
+            player,
+            error: ErrorMessage::WebSocketError,
+        })
+    }
+
+    /// Number the next message of the table.
+    fn event<'m>(&mut self, message: &'m Message) -> Event<&'m Message> {
+        self.seq += 1;
+        Event {
+            hand: self.state.hand,
+            seq: self.seq,
+            message,
+        }
+    }
+
+    /// Send a message to a single player.
+    /// Returns: the sequence number of the message
+    fn send(&mut self, player: usize, message: &Message) -> Result<u64, Error> {
+        let event = self.event(message);
+        self.send_event(player, &event)
+    }
+
+    /// Send a numbered message to a single player.
+    /// Returns: the sequence number of the message
+    fn send_event(&mut self, player: usize, event: &Event<&Message>) -> Result<u64, Error> {
+        self.client(player)?.send(event).or(Err(Error {
+            player,
+            error: ErrorMessage::WebSocketError,
+        }))?;
+        Ok(event.seq)
+    }
+
+    /// Send a message to every seated player and the spectators.
+    fn broadcast(&mut self, message: &Message) -> Result<(), Error> {
+        let event = self.event(message);
+        self.spectate(&event);
+        for (player, client) in self.clients.iter_mut().enumerate() {
+            let client = match client {
+                Some(client) => client,
+                None => continue,
+            };
+            client.send(&event).or(Err(Error {
+                player,
+                error: ErrorMessage::WebSocketError,
+            }))?;
+        }
+        Ok(())
+    }
+
+    /// Tell the table who is sitting where.
+    fn broadcast_names(&mut self) {
+        let names = (0..self.clients.len())
+            .map(|seat| self.name(seat))
+            .collect();
+        let message = Message::Players(names);
+        self.state.apply(&message);
+        self.broadcast(&message).ok();
+    }
+
+    /// Send a player the state of the table as they may see it.
+    fn send_snapshot(&mut self, player: usize) -> Result<(), Error> {
+        let snapshot = Message::StateSnapshot(self.state.snapshot(player));
+        self.send(player, &snapshot).and(Ok(()))
+    }
+
+    /// Whether the player in the given seat wants a snapshot with every
+    /// request to act.
+    fn wants_snapshots(&self, player: usize) -> bool {
+        self.clients[player]
+            .as_ref()
+            .is_some_and(|client| client.protocol.has(Capability::StateSnapshots))
+    }
+
+    /// Wait for the response of a player. Requests that can be sent at any
+    /// time are put aside until they are handled between rounds, or dropped
+    /// when the table does not take them, snapshots are sent right away.
+    /// Replies to other messages than the request are stale, and dropped.
+    /// request: the sequence number of the request
+    fn receive(&mut self, player: usize, request: u64) -> Result<Response, Error> {
+        loop {
+            let (to, response) = self
+                .client(player)?
+                .recv()
                 .or(Err(Error {
                     player,
                     error: ErrorMessage::WebSocketError,
-                })),
+                }))?
+                .decode_response()
+                .ok_or(Error {
+                    player,
+                    error: ErrorMessage::InvalidResponse,
+                })?;
+            match response {
+                Response::StandUp | Response::TopUp(_) if self.cash => {
+                    self.requests.push((player, response))
+                }
+                Response::StandUp | Response::TopUp(_) => {
+                    log!(
+                        "player {} asked for {:?} away from a cash table",
+                        player,
+                        response
+                    )
+                }
+                Response::Snapshot => self.send_snapshot(player)?,
+                _ if to.is_some_and(|to| to != request) => {
+                    log!(
+                        "player {} replied to message {:?} while asked to act",
+                        player,
+                        to
+                    )
+                }
+                response => return Ok(response),
+            }
+        }
+    }
+}
+
+impl<'a> Seating for Adapter<'a> {
+    type Connection = Player;
+
+    fn leave(&mut self, seat: usize) -> Option<Player> {
+        self.unseat(seat)
+    }
+
+    fn join(&mut self, seat: usize, connection: Player, stack: Money) {
+        self.clients[seat] = Some(connection);
+        self.state.apply(&Message::SeatChange {
+            player: seat,
+            stack,
+        });
+        self.table.joined();
+        self.broadcast_names();
+        if self.wants_snapshots(seat) {
+            self.send_snapshot(seat).ok();
+        }
+    }
+
+    fn requests(&mut self, seat: usize) -> Vec<Response> {
+        let (mut requests, others) = self.requests.drain(..).partition(|(s, _)| *s == seat);
+        self.requests = others;
+        let mut requests = requests.drain(..).map(|(_, r)| r).collect::<Vec<_>>();
+        // read whatever else the player sent without waiting for more
+        if let Some(client) = &mut self.clients[seat] {
+            if client.set_nonblocking(true).is_ok() {
+                loop {
+                    match client.recv() {
+                        Ok(frame) => requests.extend(frame.decode_response().map(|(_, r)| r)),
+                        // the client hung up
+                        Err(e) if e.kind() == ErrorKind::ConnectionAborted => {
+                            requests.push(Response::StandUp);
+                            break;
+                        }
+                        Err(_) => break,
+                    }
+                }
+                client.set_nonblocking(false).ok();
+            }
+        }
+        if requests.iter().any(|r| matches!(r, Response::Snapshot)) {
+            requests.retain(|r| !matches!(r, Response::Snapshot));
+            self.send_snapshot(seat).ok();
+        }
+        requests
+    }
+
+    fn bought_in(&mut self, seat: usize, chips: Money) {
+        if let Some(name) = self.name(seat) {
+            self.ledger.lock().unwrap().record(Record::BuyIn {
+                name,
+                table: self.table.id,
+                chips,
+            });
+        }
+    }
+
+    fn kick(&mut self, mut connection: Player) {
+        connection.close();
+    }
+
+    fn cashed_out(&mut self, seat: usize, chips: Money) {
+        if let Some(name) = self.name(seat) {
+            self.ledger.lock().unwrap().record(Record::CashOut {
+                name,
+                table: self.table.id,
+                chips,
+            });
+        }
+    }
+}
+
+impl<'a> Callback for Adapter<'a> {
+    fn callback(&mut self, message: Message) -> Result<Response, Error> {
+        if self.state.begins_hand(&message) {
+            self.state.start_hand(self.table.next_hand());
+        }
+        self.state.apply(&message);
+        match message {
+            Message::RequestAction { player, .. } | Message::RequestStraddle { player, .. } => {
+                if self.wants_snapshots(player) {
+                    self.send_snapshot(player)?;
+                }
+                let event = self.event(&message);
+                self.spectate(&event);
+                let request = self.send_event(player, &event)?;
+                let response = self.receive(player, request)?;
+                if let Response::Action(PlayerAction::Fold) = response {
+                    self.holes[player] = None;
+                }
+                Ok(response)
+            }
+            Message::Hole { player, cards } => {
+                self.holes[player] = Some(cards);
+                self.send(player, &message).and(Ok(Response::Ack))
+            }
+            Message::Seated { player, .. }
+            | Message::StateSnapshot(StateSnapshot { player, .. }) => {
+                self.send(player, &message).and(Ok(Response::Ack))
+            }
             Message::Flop(..)
             | Message::River(..)
             | Message::Turn(..)
             | Message::GameOver
+            | Message::BlindsUp { .. }
+            | Message::Eliminated { .. }
+            | Message::Standings(..)
+            | Message::SeatChange { .. }
+            | Message::PlayerActed { .. }
+            | Message::Players(..)
+            | Message::Shutdown { .. }
+            | Message::Aborted { .. }
             | Message::Showdown { .. } => {
-                for (player, client) in self.clients.iter_mut().enumerate() {
-                    client
-                        .send_message(&WsMessage::text(serde_json::to_string(&message).unwrap()))
-                        .or(Err(Error {
-                            player,
-                            error: ErrorMessage::WebSocketError,
-                        }))?;
+                match &message {
+                    Message::PlayerActed {
+                        player,
+                        amount,
+                        stack,
+                        ..
+                    } => {
+                        self.hand[*player].get_or_insert(stack + amount);
+                    }
+                    Message::BlindsUp { blinds, .. } => self.big_blind = blinds.big_blind,
+                    // a game restarts once a single player has chips left
+                    Message::GameOver => {
+                        let seats = (0..self.clients.len()).collect::<Vec<_>>();
+                        self.record_game(&seats);
+                    }
+                    Message::Standings(seats) => self.record_game(seats),
+                    // the round is undone, nobody won or lost anything
+                    Message::Aborted { .. } => self.hand.iter_mut().for_each(|start| *start = None),
+                    Message::Showdown {
+                        pot,
+                        players,
+                        stacks,
+                        ..
+                    } => {
+                        self.table
+                            .hand_played(self.state.hand, *pot, players.clone());
+                        self.record_hand(stacks);
+                        self.show_holes();
+                    }
+                    _ => {}
                 }
+                self.broadcast(&message)?;
                 Ok(Response::Ack)
             }
             Message::Error(Error { player, error }) => {
-                println!("player {:?} messed up: {:?}", player, error);
-                self.clients.remove(player);
-                // handle error
+                log!("player {:?} messed up: {:?}", player, error);
+                // kick the player, the others are sent back to the queue
+                if let Some(mut client) = self.unseat(player) {
+                    client.close();
+                }
                 Ok(Response::Ack)
             }
         }
     }
+
+    fn stopping(&mut self) -> bool {
+        self.table.closing()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::communication::{Ante, GameType, Pot, SeatStatus, Straddle, Suit};
+    use crate::score::Score;
+    use serde::de::DeserializeOwned;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpStream;
+    use websocket::client::ClientBuilder;
+    use websocket::server::NoTlsAcceptor;
+    use websocket::sync::Client;
+    use websocket::{Message as WsMessage, OwnedMessage};
+
+    /// Connect a client to the server, returning the server side as a logged
+    /// in player and the client side.
+    fn connect(
+        server: &mut Server<NoTlsAcceptor>,
+        accounts: &Accounts,
+        name: &str,
+    ) -> (Player, Client<TcpStream>) {
+        let address = format!("ws://{}", server.local_addr().unwrap());
+        let connecting =
+            thread::spawn(move || ClientBuilder::new(&address).unwrap().connect_insecure());
+        let client = server.accept().ok().unwrap().accept().ok().unwrap();
+        let player = Player {
+            connection: Box::new(client),
+            session: accounts.login(name, "").unwrap(),
+            protocol: Protocol::default(),
+        };
+        (player, connecting.join().unwrap().unwrap())
+    }
+
+    /// Answer every request to act with the given action, or disconnect when
+    /// there is none.
+    /// Returns: the messages received until the connection closed
+    fn bot(mut client: Client<TcpStream>, action: Option<PlayerAction>) -> Vec<String> {
+        let mut messages = Vec::new();
+        while let Ok(OwnedMessage::Text(message)) = client.recv_message() {
+            if message.contains("RequestAction") {
+                match action {
+                    Some(action) => {
+                        let response = serde_json::to_string(&Response::Action(action)).unwrap();
+                        client.send_message(&WsMessage::text(response)).unwrap();
+                    }
+                    None => break,
+                }
+            }
+            messages.push(message);
+        }
+        messages
+    }
+
+    #[test]
+    fn test_tcp_login() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (lobby, players) = channel();
+        let gate = Gate {
+            accounts: Accounts::open(),
+            lobby,
+            login_timeout: Duration::from_secs(5),
+            action_timeout: None,
+        };
+        gate.admit(Box::new(Lines::tcp(listener.accept().unwrap().0).unwrap()));
+
+        // MessagePack is not for plain TCP clients
+        let mut reader = BufReader::new(client.try_clone().unwrap());
+        let mut writer = client;
+        writeln!(
+            writer,
+            r#"{{"name":"alice","token":"","version":2,"capabilities":["MessagePack","CompactCards"]}}"#
+        )
+        .unwrap();
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(
+            line,
+            "{\"Accepted\":{\"version\":2,\"capabilities\":[\"CompactCards\"]}}\n"
+        );
+
+        let mut player = players.recv().unwrap();
+        assert_eq!(player.session.name(), "alice");
+        player
+            .send(&Event {
+                hand: 0,
+                seq: 1,
+                message: &Message::GameOver,
+            })
+            .unwrap();
+        line.clear();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(line, "\"GameOver\"\n");
+        writeln!(writer, r#"{{"Action":"Fold"}}"#).unwrap();
+        let response = player.recv().unwrap().decode::<Response>();
+        assert!(matches!(
+            response,
+            Some(Response::Action(PlayerAction::Fold))
+        ));
+    }
+
+    /// Connect a player talking JSON lines, returning the server side and
+    /// the client side to read from and write to.
+    fn connect_lines(
+        listener: &TcpListener,
+        accounts: &Accounts,
+        name: &str,
+        protocol: Protocol,
+    ) -> (Player, BufReader<TcpStream>, TcpStream) {
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let player = Player {
+            connection: Box::new(Lines::tcp(listener.accept().unwrap().0).unwrap()),
+            session: accounts.login(name, "").unwrap(),
+            protocol,
+        };
+        (player, BufReader::new(client.try_clone().unwrap()), client)
+    }
+
+    fn read_line<T: DeserializeOwned>(reader: &mut BufReader<TcpStream>) -> T {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        serde_json::from_str(&line).unwrap()
+    }
+
+    /// An adapter for a table with two empty seats.
+    fn adapter(seats: &mut Vec<Option<Player>>) -> Adapter<'_> {
+        let tables = Arc::new(Mutex::new(Tables::default()));
+        let handle = TableHandle { id: 0, tables };
+        let ledger = Arc::new(Mutex::new(Ledger::in_memory()));
+        Adapter::new(seats, handle, ledger, 2)
+    }
+
+    #[test]
+    fn test_address_in_use() {
+        let taken = TcpListener::bind("127.0.0.1:0").unwrap();
+        let config = Config {
+            port: taken.local_addr().unwrap().port(),
+            status: false,
+            ..Config::default()
+        };
+        let result = run_server(
+            config,
+            Accounts::open(),
+            Ledger::in_memory(),
+            Shutdown::new(),
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_snapshots() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let accounts = Accounts::open();
+        let connect = |name, capabilities: &[Capability]| {
+            let protocol = Protocol::negotiate(2, capabilities).unwrap();
+            connect_lines(&listener, &accounts, name, protocol)
+        };
+        let read = read_line::<Message>;
+        let (alice, mut alice_reader, _alice_writer) =
+            connect("alice", &[Capability::StateSnapshots]);
+        let (bob, mut bob_reader, mut bob_writer) = connect("bob", &[]);
+
+        let mut seats = vec![None, None];
+        let mut adapter = adapter(&mut seats);
+        adapter.join(0, alice, 100);
+        adapter.join(1, bob, 100);
+        assert!(matches!(read(&mut alice_reader), Message::Players(..)));
+        match read(&mut alice_reader) {
+            Message::StateSnapshot(snapshot) => {
+                assert_eq!(snapshot.seats[0].name.as_deref(), Some("alice"));
+                assert_eq!(snapshot.seats[0].stack, 100);
+                // bob is not seated yet
+                assert_eq!(snapshot.seats[1].status, SeatStatus::Empty);
+            }
+            message => panic!("expected a snapshot, got {:?}", message),
+        }
+
+        let card = |rank| Card {
+            rank,
+            suit: Suit::Hearts,
+        };
+        for (player, cards) in [(0, (card(2), card(3))), (1, (card(4), card(5)))] {
+            adapter.callback(Message::Hole { player, cards }).unwrap();
+        }
+        let request = |player| Message::RequestAction {
+            player,
+            bets: vec![Some(0), Some(0)],
+            pot: 0,
+            min_raise: Some(2),
+        };
+
+        // bob has to ask for it, and still gets to answer the request
+        writeln!(bob_writer, "\"Snapshot\"\n{{\"Action\":\"Call\"}}").unwrap();
+        let response = adapter.callback(request(1)).unwrap();
+        assert!(matches!(response, Response::Action(PlayerAction::Call)));
+        assert!(matches!(read(&mut bob_reader), Message::Players(..)));
+        assert!(matches!(read(&mut bob_reader), Message::Hole { .. }));
+        assert!(matches!(
+            read(&mut bob_reader),
+            Message::RequestAction { .. }
+        ));
+        match read(&mut bob_reader) {
+            Message::StateSnapshot(snapshot) => {
+                assert_eq!(snapshot.player, 1);
+                assert_eq!(snapshot.hand, 1);
+                assert_eq!(snapshot.actor, Some(1));
+                assert_eq!(snapshot.hole, Some((card(4), card(5))));
+                assert_eq!(snapshot.seats[1].status, SeatStatus::Active);
+            }
+            message => panic!("expected a snapshot, got {:?}", message),
+        }
+    }
+
+    #[test]
+    fn test_cash_snapshots() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let accounts = Accounts::open();
+        let buy_in = BuyIn { min: 50, max: 200 };
+        let request = TableRequest {
+            n_players: 2,
+            small_blind: 1,
+            big_blind: 2,
+            stack: 100,
+            game_type: GameType::NoLimit,
+            ante: Ante::None,
+            straddle: Straddle::None,
+            format: Format::CashTable(buy_in),
+        };
+        let tables = Arc::new(Mutex::new(Tables::default()));
+        let id = tables
+            .lock()
+            .unwrap()
+            .open(request.clone(), TableStatus::Running);
+        let handle = TableHandle { id, tables };
+        let ledger = Arc::new(Mutex::new(Ledger::in_memory()));
+
+        // both players check down the first hand and hang up when asked to
+        // act in the second, collecting the snapshots they were sent
+        let (arrivals, arriving) = channel();
+        let mut bots = Vec::new();
+        for (name, chips) in [("alice", 150), ("bob", 80)] {
+            let protocol = Protocol::negotiate(2, &[Capability::StateSnapshots]).unwrap();
+            let (player, mut reader, mut writer) =
+                connect_lines(&listener, &accounts, name, protocol);
+            arrivals.send((player, chips)).unwrap();
+            bots.push(thread::spawn(move || {
+                let mut snapshots = Vec::new();
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap_or(0) > 0 {
+                    match serde_json::from_str(&line).unwrap() {
+                        Message::StateSnapshot(snapshot) if snapshot.hand > 1 => break,
+                        Message::StateSnapshot(snapshot) => snapshots.push(snapshot),
+                        Message::RequestAction { .. } => {
+                            writeln!(writer, "{{\"Action\":\"Call\"}}").unwrap()
+                        }
+                        _ => {}
+                    }
+                    line.clear();
+                }
+                snapshots
+            }));
+        }
+        drop(arrivals);
+        // players that leave the table hang up as well
+        let (lobby, _) = channel();
+        let table =
+            thread::spawn(move || do_cash_table(handle, ledger, request, buy_in, arriving, lobby));
+        let snapshots = bots
+            .into_iter()
+            .map(|bot| bot.join().unwrap())
+            .collect::<Vec<_>>();
+        table.join().unwrap();
+
+        // a player sitting down, or coming back after losing the connection,
+        // sees the stacks of everybody seated
+        let alice_seated = &snapshots[0][0];
+        assert_eq!(alice_seated.seats[0].stack, 150);
+        assert_eq!(alice_seated.seats[1].status, SeatStatus::Empty);
+        let bob_seated = &snapshots[1][0];
+        let stacks = bob_seated.seats.iter().map(|s| s.stack).collect::<Vec<_>>();
+        assert_eq!(stacks, vec![150, 80]);
+
+        // the first hand is played with those stacks
+        for (snapshots, chips) in snapshots.iter().zip([150, 80]) {
+            let first = &snapshots[1];
+            assert_eq!(first.hand, 1);
+            let seat = &first.seats[first.player];
+            assert_eq!(seat.stack + seat.bet, chips);
+            assert!(first.seats.iter().all(|s| s.status == SeatStatus::Active));
+        }
+    }
+
+    #[test]
+    fn test_spectate() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let accounts = Accounts::open();
+        let request = TableRequest {
+            n_players: 2,
+            small_blind: 1,
+            big_blind: 2,
+            stack: 100,
+            game_type: GameType::NoLimit,
+            ante: Ante::None,
+            straddle: Straddle::None,
+            format: Format::Cash,
+        };
+        let tables = Arc::new(Mutex::new(Tables::default()));
+        let id = tables.lock().unwrap().open(request, TableStatus::Running);
+        let handle = TableHandle {
+            id,
+            tables: tables.clone(),
+        };
+        let ledger = Arc::new(Mutex::new(Ledger::in_memory()));
+        let mut seats = vec![None, None];
+        let mut adapter = Adapter::new(&mut seats, handle, ledger, 2);
+        // keep the connections of the players open
+        let mut connections = vec![];
+        for (seat, name) in ["alice", "bob"].iter().enumerate() {
+            let (player, reader, writer) =
+                connect_lines(&listener, &accounts, name, Protocol::default());
+            adapter.join(seat, player, 100);
+            connections.push((reader, writer));
+        }
+
+        // the spectator speaks version 3 with compact cards
+        let protocol = Protocol::negotiate(3, &[Capability::CompactCards]).unwrap();
+        let (spectator, mut reader, _writer) =
+            connect_lines(&listener, &accounts, "carol", protocol);
+        let (watcher, messages) = channel();
+        assert!(tables.lock().unwrap().watch(id, watcher));
+        let (lobby, back) = channel();
+        let delay = Duration::from_millis(200);
+        let watching = thread::spawn(move || spectate(spectator, delay, messages, lobby));
+
+        let card = |rank, suit| Card { rank, suit };
+        let board = [
+            card(2, Suit::Clubs),
+            card(7, Suit::Diamonds),
+            card(9, Suit::Clubs),
+            card(11, Suit::Hearts),
+            card(3, Suit::Spades),
+        ];
+        let holes = [
+            (card(14, Suit::Spades), card(14, Suit::Hearts)),
+            (card(13, Suit::Spades), card(13, Suit::Hearts)),
+        ];
+        let start = Instant::now();
+        for (player, &cards) in holes.iter().enumerate() {
+            adapter.callback(Message::Hole { player, cards }).unwrap();
+        }
+        adapter
+            .callback(Message::Flop(board[0], board[1], board[2]))
+            .unwrap();
+        let mut hand = vec![holes[0].0, holes[0].1];
+        hand.extend_from_slice(&board);
+        let score = Score::calculate(hand);
+        adapter
+            .callback(Message::Showdown {
+                score,
+                pot: 4,
+                players: vec![0],
+                stacks: vec![102, 98],
+                pots: vec![Pot {
+                    amount: 4,
+                    players: vec![0],
+                    score,
+                }],
+            })
+            .unwrap();
+        drop(adapter);
+
+        // the hole cards are only shown at the showdown, everything comes in
+        // late and in the form the spectator asked for
+        let event = read_line::<serde_json::Value>(&mut reader);
+        assert!(start.elapsed() >= delay);
+        assert_eq!(event["hand"], 1);
+        assert_eq!(event["message"]["Flop"][0], "2c");
+        for (player, cards) in [(0, ["As", "Ah"]), (1, ["Ks", "Kh"])] {
+            let event = read_line::<serde_json::Value>(&mut reader);
+            assert_eq!(event["message"]["Hole"]["player"], player);
+            assert_eq!(event["message"]["Hole"]["cards"][0], cards[0]);
+            assert_eq!(event["message"]["Hole"]["cards"][1], cards[1]);
+        }
+        let event = read_line::<Event>(&mut reader);
+        assert!(matches!(event.message, Message::Showdown { .. }));
+
+        // the spectator goes back to the lobby once the table is gone
+        watching.join().unwrap();
+        assert_eq!(back.recv().unwrap().session.name(), "carol");
+    }
+
+    #[test]
+    fn test_stale_reply() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let accounts = Accounts::open();
+        let protocol = Protocol::negotiate(3, &[]).unwrap();
+        let (alice, mut reader, mut writer) =
+            connect_lines(&listener, &accounts, "alice", protocol);
+        let mut seats = vec![None, None];
+        let mut adapter = adapter(&mut seats);
+        adapter.join(0, alice, 100);
+        let event = read_line::<Event>(&mut reader);
+        assert!(matches!(event.message, Message::Players(..)));
+        assert_eq!((event.hand, event.seq), (0, 1));
+
+        let request = Message::RequestAction {
+            player: 0,
+            bets: vec![Some(0), Some(0)],
+            pot: 0,
+            min_raise: Some(2),
+        };
+        // a reply that is too late for the previous request is dropped
+        writer
+            .write_all(
+                br#"{"to":1,"response":{"Action":"Fold"}}
+{"Action":{"Raise":4}}
+"#,
+            )
+            .unwrap();
+        let response = adapter.callback(request).unwrap();
+        assert!(matches!(response, Response::Action(PlayerAction::Raise(4))));
+        let event = read_line::<Event>(&mut reader);
+        assert!(matches!(event.message, Message::RequestAction { .. }));
+        assert_eq!((event.hand, event.seq), (1, 2));
+
+        let request = Message::RequestAction {
+            player: 0,
+            bets: vec![Some(4), Some(2)],
+            pot: 0,
+            min_raise: Some(2),
+        };
+        writer
+            .write_all(
+                br#"{"to":2,"response":{"Action":"Fold"}}
+{"to":3,"response":{"Action":"Call"}}
+"#,
+            )
+            .unwrap();
+        let response = adapter.callback(request).unwrap();
+        assert!(matches!(response, Response::Action(PlayerAction::Call)));
+        let event = read_line::<Event>(&mut reader);
+        assert_eq!((event.hand, event.seq), (1, 3));
+
+        // only cash tables put requests aside for between the rounds
+        let request_action = |player| Message::RequestAction {
+            player,
+            bets: vec![Some(4), Some(4)],
+            pot: 0,
+            min_raise: Some(2),
+        };
+        writer
+            .write_all(b"\"StandUp\"\n{\"TopUp\":10}\n{\"Action\":\"Fold\"}\n")
+            .unwrap();
+        let response = adapter.callback(request_action(0)).unwrap();
+        assert!(matches!(response, Response::Action(PlayerAction::Fold)));
+        assert!(adapter.requests.is_empty());
+        adapter.cash = true;
+        writer
+            .write_all(b"\"StandUp\"\n{\"Action\":\"Fold\"}\n")
+            .unwrap();
+        adapter.callback(request_action(0)).unwrap();
+        assert_eq!(adapter.requests.len(), 1);
+    }
+
+    #[test]
+    fn test_disconnect() {
+        let mut server = Server::bind("127.0.0.1:0").unwrap();
+        let accounts = Accounts::open();
+        let request = TableRequest {
+            n_players: 3,
+            small_blind: 1,
+            big_blind: 2,
+            stack: 100,
+            game_type: GameType::NoLimit,
+            ante: Ante::None,
+            straddle: Straddle::None,
+            format: Format::Cash,
+        };
+        let tables = Arc::new(Mutex::new(Tables::default()));
+        let id = tables
+            .lock()
+            .unwrap()
+            .open(request.clone(), TableStatus::Running);
+        let handle = TableHandle { id, tables };
+        let ledger = Arc::new(Mutex::new(Ledger::in_memory()));
+
+        // bob disconnects the first time he has to act
+        let mut players = Vec::new();
+        let mut bots = Vec::new();
+        for (name, action) in [
+            ("alice", Some(PlayerAction::Call)),
+            ("bob", None),
+            ("carol", Some(PlayerAction::Call)),
+        ] {
+            let (player, client) = connect(&mut server, &accounts, name);
+            players.push(player);
+            bots.push(thread::spawn(move || bot(client, action)));
+        }
+        let (queue_tx, queue_rx) = channel();
+        let (lobby_tx, lobby_rx) = channel();
+        do_game(
+            request.clone(),
+            players,
+            queue_tx,
+            lobby_tx,
+            handle.clone(),
+            ledger.clone(),
+        );
+
+        // the others keep their seats and go back to the queue
+        let mut queued = queue_rx
+            .try_iter()
+            .map(|(table, player)| {
+                assert_eq!(table, request);
+                player
+            })
+            .collect::<Vec<_>>();
+        queued.sort_by(|a, b| a.session.name().cmp(b.session.name()));
+        let names = queued.iter().map(|p| p.session.name()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["alice", "carol"]);
+        assert!(lobby_rx.try_recv().is_err());
+        assert!(accounts.login("bob", "").is_ok());
+        drop(queued);
+        let messages = bots.remove(0).join().unwrap();
+        let seats = serde_json::to_string(&Message::Players(vec![
+            Some(String::from("alice")),
+            None,
+            Some(String::from("carol")),
+        ]))
+        .unwrap();
+        assert!(messages.contains(&seats), "{:?}", messages);
+
+        // requests still reach the player in the seat they were meant for
+        // after someone was kicked
+        let (alice, alice_client) = connect(&mut server, &accounts, "alice");
+        let (bob, _) = connect(&mut server, &accounts, "bob");
+        let (carol, carol_client) = connect(&mut server, &accounts, "carol");
+        let mut seats = vec![Some(alice), Some(bob), Some(carol)];
+        let mut adapter = Adapter::new(&mut seats, handle, ledger, 2);
+        adapter
+            .callback(Message::Error(Error {
+                player: 1,
+                error: ErrorMessage::WebSocketError,
+            }))
+            .unwrap();
+        let carol_bot = thread::spawn(move || bot(carol_client, Some(PlayerAction::Raise(7))));
+        let response = adapter
+            .callback(Message::RequestAction {
+                player: 2,
+                bets: vec![Some(1), None, Some(2)],
+                pot: 3,
+                min_raise: Some(2),
+            })
+            .unwrap();
+        assert!(matches!(response, Response::Action(PlayerAction::Raise(7))));
+        drop(adapter);
+        assert!(seats[1].is_none());
+        drop(seats);
+        drop(alice_client);
+        carol_bot.join().unwrap();
+    }
 }
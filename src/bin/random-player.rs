@@ -1,5 +1,6 @@
 use ::poker::{
-    GameType, Message as PokerMessage, PlayerAction, RequestTable, Response, TableRequest,
+    Ante, Bot, Client, Event, Format, GameState, GameType, PlayerAction, RequestTable, Straddle,
+    TableRequest,
 };
 use std::fs::File;
 use std::io::Write;
@@ -7,8 +8,6 @@ use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::{channel, Sender};
 use std::sync::Arc;
 use std::thread;
-use websocket::client::ClientBuilder;
-use websocket::{Message, OwnedMessage};
 
 // const CONNECTION: &str = "wss://wss.sebastiaanvermeulen.nl/pokerroom";
 const CONNECTION: &str = "ws://127.0.0.1:2794";
@@ -36,58 +35,79 @@ fn main() {
     }
 }
 
+/// Plays at random, and logs every message of the table.
+struct RandomPlayer {
+    player: usize,
+    running: Arc<AtomicBool>,
+    tx: Sender<String>,
+    count: usize,
+}
+
+impl Bot for RandomPlayer {
+    fn act(&mut self, _state: &GameState) -> PlayerAction {
+        let action = match rand::random::<u8>() {
+            0..=55 => PlayerAction::Raise(2),
+            56..=100 => PlayerAction::Fold,
+            _ => PlayerAction::Call,
+        };
+        self.tx
+            .send(format!("[Player {}]     <sent> {:?}", self.player, action))
+            .unwrap();
+        action
+    }
+
+    // every message has the hand and its sequence number with it, so the
+    // logs of the players line up
+    fn observe(&mut self, _state: &GameState, event: &Event) {
+        self.count += 1;
+        let event = serde_json::to_string(event).unwrap();
+        self.tx
+            .send(format!("[Player {}] <received> {}", self.player, event))
+            .unwrap();
+    }
+
+    fn stopping(&mut self) -> bool {
+        if self.count > 100 {
+            self.tx.send(format!("[Player {}] ########## Got a thousand messages, you get the point. Shutting down client", self.player)).unwrap();
+            self.running.store(false, Ordering::SeqCst);
+        }
+        !self.running.load(Ordering::SeqCst)
+    }
+}
+
 fn run_player(player: usize, running: Arc<AtomicBool>, tx: Sender<String>) {
-    let mut client = ClientBuilder::new(CONNECTION)
-        .expect("could not build connection")
-        .add_protocol("rust-websocket")
-        .connect(None)
+    // log in, the server has to run without accounts
+    let mut client = Client::connect(CONNECTION, &format!("random player {}", player), "")
         .expect("connect failed");
 
     // request to join a table
-    let serialized = serde_json::to_string(&RequestTable::Table(TableRequest {
+    let request = RequestTable::Table(TableRequest {
         n_players: 3,
         small_blind: 1,
         big_blind: 2,
         stack: 40,
         game_type: GameType::NoLimit,
-    }))
+        ante: Ante::None,
+        straddle: Straddle::None,
+        format: Format::Cash,
+    });
+    tx.send(format!(
+        "[Player {}]     <sent> {}",
+        player,
+        serde_json::to_string(&request).unwrap()
+    ))
     .unwrap();
-    tx.send(format!("[Player {}]     <sent> {}", player, serialized))
-        .unwrap();
-    client.send_message(&Message::text(serialized)).ok();
+    client.join(request).expect("could not ask for a table");
 
-    let mut count = 0;
-    while let Ok(msg) = client.recv_message() {
-        if !running.load(Ordering::SeqCst) {
-            break;
-        }
-        count += 1;
-        if count > 100 {
-            tx.send(format!("[Player {}] ########## Got a thousand messages, you get the point. Shutting down client", player)).unwrap();
-            client.send_message(&Message::close()).ok();
-            running.store(false, Ordering::SeqCst);
-            break;
-        }
-        match msg {
-            OwnedMessage::Text(t) => {
-                tx.send(format!("[Player {}] <received> {}", player, t))
-                    .unwrap();
-                if let Ok(PokerMessage::RequestAction { .. }) =
-                    serde_json::from_str::<PokerMessage>(&t)
-                {
-                    let action = match rand::random::<u8>() {
-                        0..=55 => PlayerAction::Raise(2),
-                        56..=100 => PlayerAction::Fold,
-                        _ => PlayerAction::Call,
-                    };
-                    let serialized = serde_json::to_string(&Response::Action(action)).unwrap();
-                    tx.send(format!("[Player {}]     <sent> {}", player, serialized))
-                        .unwrap();
-                    let msg = Message::text(serialized);
-                    client.send_message(&msg).ok();
-                }
-            }
-            _ => break,
-        }
+    let mut bot = RandomPlayer {
+        player,
+        running,
+        tx,
+        count: 0,
+    };
+    if let Err(e) = client.play(&mut bot) {
+        bot.tx
+            .send(format!("[Player {}] lost the connection: {:?}", player, e))
+            .unwrap();
     }
 }
@@ -0,0 +1,10 @@
+use ::poker::protocol_schema;
+
+/// Print the JSON Schema of the protocol, to validate messages or generate
+/// clients in other languages with.
+fn main() {
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&protocol_schema()).unwrap()
+    );
+}
@@ -1,14 +1,23 @@
-use ::poker::run_server;
+use ::poker::{init_log, run_server, Accounts, Config, Ledger, Shutdown};
 use clap::{App, Arg};
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+use std::process;
 use std::thread;
 
-// TODO also serve a page that updates the waiting list
-
 fn main() -> Result<(), ()> {
     let matches = App::new("Pokerroom server")
         .version("0.1")
         .author("Sebastiaan Vermeulen <mail@sebastiaanvermeulen.nl>")
         .about("A pokerroom websocket server")
+        .arg(
+            Arg::with_name("config")
+                .short("c")
+                .long("config")
+                .value_name("FILE")
+                .help("TOML file with the configuration, see server.example.toml. The flags below override it")
+                .takes_value(true),
+        )
         .arg(
             Arg::with_name("address")
                 .short("a")
@@ -25,17 +34,172 @@ fn main() -> Result<(), ()> {
                 .help("port to use, defaults to 2794")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("status-port")
+                .short("s")
+                .long("status-port")
+                .value_name("NUMBER")
+                .help("port to serve the status page on, defaults to 2795")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("tcp-port")
+                .long("tcp-port")
+                .value_name("NUMBER")
+                .help("also accept clients sending a JSON message per line over plain TCP on this port")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("bot")
+                .long("bot")
+                .value_name("COMMAND")
+                .help("run a bot that talks JSON lines over stdin and stdout, can be given more than once")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("no-status")
+                .long("no-status")
+                .help("do not serve a status page"),
+        )
+        .arg(
+            Arg::with_name("max-connections")
+                .long("max-connections")
+                .value_name("NUMBER")
+                .help("the most players that can be logged in at once")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("action-timeout")
+                .long("action-timeout")
+                .value_name("SECONDS")
+                .help("how long players get to act before they are kicked")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("accounts")
+                .long("accounts")
+                .value_name("FILE")
+                .help("JSON file with the token of every player name, anyone can log in without it")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("ledger")
+                .long("ledger")
+                .value_name("FILE")
+                .help("file to keep bankrolls and results in, kept in memory without it")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("log")
+                .long("log")
+                .value_name("FILE")
+                .help("file to append the log to instead of writing it to stdout")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("quiet")
+                .short("q")
+                .long("quiet")
+                .help("do not log anything"),
+        )
         .get_matches();
 
-    let address = matches.value_of("address").unwrap_or("127.0.0.1");
-    let port = matches.value_of("port").unwrap_or("2794");
-    let loc = String::from(address) + ":" + port;
+    let mut config = match matches.value_of("config") {
+        Some(path) => Config::load(path).map_err(|e| println!("could not read {}: {}", path, e))?,
+        None => Config::default(),
+    };
+    if let Some(address) = matches.value_of("address") {
+        config.address = String::from(address);
+    }
+    config.port = number(matches.value_of("port"), config.port)?;
+    config.status_port = number(matches.value_of("status-port"), config.status_port)?;
+    if let Some(port) = matches.value_of("tcp-port") {
+        config.tcp_port = Some(number(Some(port), 0)?);
+    }
+    if let Some(commands) = matches.values_of("bot") {
+        config
+            .bots
+            .extend(commands.map(|command| command.split_whitespace().map(String::from).collect()));
+    }
+    if matches.is_present("no-status") {
+        config.status = false;
+    }
+    if let Some(max) = matches.value_of("max-connections") {
+        config.max_connections = Some(number(Some(max), 0)?);
+    }
+    if let Some(timeout) = matches.value_of("action-timeout") {
+        config.timeouts.action = Some(number(Some(timeout), 0)?);
+    }
+    if let Some(path) = matches.value_of("accounts") {
+        config.accounts = Some(path.into());
+    }
+    if let Some(path) = matches.value_of("ledger") {
+        config.ledger = Some(path.into());
+    }
+    if let Some(path) = matches.value_of("log") {
+        config.log.file = Some(path.into());
+    }
+    if matches.is_present("quiet") {
+        config.log.quiet = true;
+    }
+
+    init_log(&config.log).map_err(|e| println!("could not open the log: {}", e))?;
+
+    let accounts = match &config.accounts {
+        Some(path) => Accounts::load(path)
+            .map_err(|e| println!("could not read {}: {}", path.display(), e))?,
+        None => Accounts::open(),
+    };
+
+    let ledger = match &config.ledger {
+        Some(path) => {
+            Ledger::open(path).map_err(|e| println!("could not read {}: {}", path.display(), e))?
+        }
+        None => Ledger::in_memory(),
+    };
+
+    // the first interrupt lets the tables finish their round, the second
+    // one stops right away
+    let shutdown = Shutdown::new();
+    let stop = shutdown.clone();
+    let mut signals = Signals::new([SIGINT, SIGTERM])
+        .map_err(|e| println!("could not listen for signals: {}", e))?;
+    thread::spawn(move || {
+        for _ in signals.forever() {
+            if stop.is_shut_down() {
+                process::exit(1);
+            }
+            println!("shutting down, interrupt again to stop right away");
+            stop.shut_down();
+        }
+    });
 
     let server = thread::spawn(move || {
-        println!("server started on {}", loc);
-        run_server(&loc);
+        println!("server started on {}", config.address());
+        if let Some(tcp_address) = config.tcp_address() {
+            println!("JSON lines over TCP on {}", tcp_address);
+        }
+        if let Some(status_address) = config.status_address() {
+            println!("status page on http://{}/", status_address);
+        }
+        run_server(config, accounts, ledger, shutdown)
     });
 
-    // do not end program
-    server.join().or(Err(()))
+    // do not end program until the server shut down
+    server
+        .join()
+        .or(Err(()))?
+        .map_err(|e| println!("could not run the server: {}", e))
+}
+
+/// Parse a number given on the command line, or keep the default.
+fn number<T: std::str::FromStr>(value: Option<&str>, default: T) -> Result<T, ()> {
+    match value {
+        Some(value) => value
+            .parse()
+            .map_err(|_| println!("not a number: {}", value)),
+        None => Ok(default),
+    }
 }
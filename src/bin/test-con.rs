@@ -1,10 +1,5 @@
-use ::poker::{
-    run_server, GameType, Message as PokerMessage, PlayerAction, RequestTable, Response,
-    TableRequest,
-};
-use std::thread;
 use websocket::client::ClientBuilder;
-use websocket::{Message, OwnedMessage};
+use websocket::Message;
 
 const CONNECTION: &str = "ws://ws.sebastiaanvermeulen.nl/pokerroom";
 
@@ -0,0 +1,432 @@
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::time::Duration;
+
+use crate::communication::{BuyIn, Message, Money, Response, Seating, TableRequest};
+use crate::engine::Table;
+
+/// A cash game table that keeps running while players come and go. Players
+/// sit down, stand up and top up their stack between rounds, and a round is
+/// played whenever at least two seated players have chips.
+pub struct CashTable<T>
+where
+    T: Seating,
+{
+    id: usize,
+    buy_in: BuyIn,
+    table: Table<T>,
+    // the entry number of the player in every seat, None for empty seats
+    entries: Vec<Option<usize>>,
+    next_entry: usize,
+}
+
+impl<T> CashTable<T>
+where
+    T: Seating,
+{
+    /// Create an empty table with a seat for each of the requested players.
+    pub fn new(
+        id: usize,
+        table_request: &TableRequest,
+        buy_in: BuyIn,
+        callback: T,
+    ) -> CashTable<T> {
+        CashTable {
+            id,
+            buy_in,
+            table: Table::new(
+                table_request.game_type,
+                table_request.small_blind,
+                table_request.big_blind,
+                table_request.ante,
+                table_request.straddle,
+                vec![0; table_request.n_players],
+                callback,
+            ),
+            entries: vec![None; table_request.n_players],
+            next_entry: 0,
+        }
+    }
+
+    /// Keep playing rounds. New players arrive with their buy-in through
+    /// `arrivals` and wait there until a seat is free. Players that stand up
+    /// or bust are handed to `release`, players that make an illegal move are
+    /// kicked and the round they messed up is undone. Returns when no more
+    /// players can arrive and there are not enough players left to play,
+    /// when the callback asks to stop, or when a round panics, in which case
+    /// the players leave with the stacks they started the round with.
+    pub fn play<F>(mut self, arrivals: &Receiver<(T::Connection, Money)>, mut release: F)
+    where
+        F: FnMut(T::Connection),
+    {
+        while !self.table.stopped() {
+            while let Some(seat) = self.entries.iter().position(Option::is_none) {
+                match arrivals.try_recv() {
+                    Ok(arrival) => self.sit_down(seat, arrival),
+                    Err(_) => break,
+                }
+            }
+            self.handle_requests(&mut release);
+
+            if self.table.stacks().iter().filter(|&&s| s > 0).count() < 2 {
+                // with every seat taken nobody can arrive to play against,
+                // which only happens at a table with a single seat
+                let seat = match self.entries.iter().position(Option::is_none) {
+                    Some(seat) => seat,
+                    None => break,
+                };
+                // wait for players, keep handling requests of a lonely player
+                let arrival = if self.entries.iter().all(Option::is_none) {
+                    arrivals.recv().or(Err(RecvTimeoutError::Disconnected))
+                } else {
+                    arrivals.recv_timeout(Duration::from_secs(1))
+                };
+                match arrival {
+                    Ok(arrival) => self.sit_down(seat, arrival),
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+                continue;
+            }
+
+            let stacks = self.table.stacks();
+            let result = match self.table.play_round_isolated() {
+                Some(result) => result,
+                None => break,
+            };
+            if let Err(e) = result {
+                log!(
+                    "player {:?} at table {} messed up: {:?}",
+                    e.player,
+                    self.id,
+                    e.error
+                );
+                for (seat, &stack) in stacks.iter().enumerate() {
+                    self.table.set_stack(seat, stack);
+                }
+                self.table.abort();
+                if let Some(connection) = self.stand_up(e.player) {
+                    self.table.callback_mut().kick(connection);
+                }
+            }
+        }
+
+        for seat in 0..self.entries.len() {
+            if let Some(connection) = self.stand_up(seat) {
+                release(connection);
+            }
+        }
+    }
+
+    /// Seat a new player with their buy-in, kept within the limits.
+    fn sit_down(&mut self, seat: usize, (connection, buy_in): (T::Connection, Money)) {
+        let stack = buy_in.max(self.buy_in.min).min(self.buy_in.max);
+        self.table.set_stack(seat, stack);
+        self.entries[seat] = Some(self.next_entry);
+        let callback = self.table.callback_mut();
+        callback.join(seat, connection, stack);
+        callback.bought_in(seat, stack);
+        callback
+            .callback(Message::Seated {
+                table: self.id,
+                player: seat,
+                entrant: self.next_entry,
+            })
+            .ok();
+        callback
+            .callback(Message::SeatChange {
+                player: seat,
+                stack,
+            })
+            .ok();
+        self.next_entry += 1;
+    }
+
+    /// Empty a seat and inform the other players.
+    /// Returns: the connection of the player that stood up
+    fn stand_up(&mut self, seat: usize) -> Option<T::Connection> {
+        self.entries[seat].take()?;
+        let stack = self.table.stacks()[seat];
+        self.table.set_stack(seat, 0);
+        let callback = self.table.callback_mut();
+        callback.cashed_out(seat, stack);
+        let connection = callback.leave(seat);
+        callback
+            .callback(Message::SeatChange {
+                player: seat,
+                stack: 0,
+            })
+            .ok();
+        connection
+    }
+
+    /// Top up the stacks of players that asked for it, and release players
+    /// that want to stand up or have busted.
+    fn handle_requests<F>(&mut self, release: &mut F)
+    where
+        F: FnMut(T::Connection),
+    {
+        for seat in 0..self.entries.len() {
+            if self.entries[seat].is_none() {
+                continue;
+            }
+            let mut leaves = false;
+            for request in self.table.callback_mut().requests(seat) {
+                match request {
+                    Response::StandUp => leaves = true,
+                    Response::TopUp(chips) => {
+                        let stack = self.table.stacks()[seat];
+                        let topped_up = stack.saturating_add(chips).min(self.buy_in.max).max(stack);
+                        if topped_up > stack {
+                            self.table.set_stack(seat, topped_up);
+                            let callback = self.table.callback_mut();
+                            callback.bought_in(seat, topped_up - stack);
+                            callback
+                                .callback(Message::SeatChange {
+                                    player: seat,
+                                    stack: topped_up,
+                                })
+                                .ok();
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            if leaves || self.table.stacks()[seat] == 0 {
+                if let Some(connection) = self.stand_up(seat) {
+                    release(connection);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::communication::{Ante, Callback, Error, Format, GameType, PlayerAction, Straddle};
+    use std::collections::HashMap;
+    use std::sync::mpsc::channel;
+
+    /// Calls every bet, except for the cheater that raises more than anyone
+    /// can. The connection is the player's number, and players stand up
+    /// after a fixed number of rounds.
+    struct Seats<'a> {
+        cheater: Option<usize>,
+        seats: Vec<Option<usize>>,
+        rounds: usize,
+        // the round after which each player stands up
+        leave_after: HashMap<usize, usize>,
+        // top ups to ask for in the first round a player is seated
+        top_ups: HashMap<usize, Money>,
+        seat_changes: &'a mut Vec<(usize, Money)>,
+        // chips brought to and taken from the table
+        chips: &'a mut (Money, Money),
+        // the stacks of every undone round, and the players kicked
+        aborted: &'a mut Vec<Vec<Money>>,
+        kicked: &'a mut Vec<usize>,
+    }
+
+    impl<'a> Callback for Seats<'a> {
+        fn callback(&mut self, message: Message) -> Result<Response, Error> {
+            match message {
+                Message::RequestAction { player, .. } => {
+                    assert!(self.seats[player].is_some(), "asked an empty seat to act");
+                    if self.seats[player] == self.cheater {
+                        return Ok(Response::Action(PlayerAction::Raise(Money::MAX)));
+                    }
+                    return Ok(Response::Action(PlayerAction::Call));
+                }
+                Message::Aborted { stacks } => self.aborted.push(stacks),
+                Message::Showdown { .. } => self.rounds += 1,
+                Message::SeatChange { player, stack } => self.seat_changes.push((player, stack)),
+                Message::Seated {
+                    player, entrant, ..
+                } => {
+                    assert_eq!(self.seats[player], Some(entrant))
+                }
+                _ => {}
+            }
+            Ok(Response::Ack)
+        }
+    }
+
+    impl<'a> Seating for Seats<'a> {
+        type Connection = usize;
+
+        fn leave(&mut self, seat: usize) -> Option<usize> {
+            self.seats[seat].take()
+        }
+
+        fn join(&mut self, seat: usize, connection: usize, _stack: Money) {
+            assert!(self.seats[seat].is_none(), "seated on an occupied seat");
+            self.seats[seat] = Some(connection);
+        }
+
+        fn requests(&mut self, seat: usize) -> Vec<Response> {
+            let player = self.seats[seat].unwrap();
+            let mut requests = Vec::new();
+            if let Some(chips) = self.top_ups.remove(&player) {
+                requests.push(Response::TopUp(chips));
+            }
+            if self.rounds >= self.leave_after[&player] {
+                requests.push(Response::StandUp);
+            }
+            requests
+        }
+
+        fn bought_in(&mut self, seat: usize, chips: Money) {
+            assert!(self.seats[seat].is_some(), "empty seat bought in");
+            self.chips.0 += chips;
+        }
+
+        fn cashed_out(&mut self, seat: usize, chips: Money) {
+            assert!(self.seats[seat].is_some(), "empty seat cashed out");
+            self.chips.1 += chips;
+        }
+
+        fn kick(&mut self, connection: usize) {
+            self.kicked.push(connection);
+        }
+    }
+
+    #[test]
+    fn test_cash_table() {
+        let buy_in = BuyIn { min: 50, max: 200 };
+        let request = TableRequest {
+            n_players: 3,
+            small_blind: 1,
+            big_blind: 2,
+            stack: 100,
+            game_type: GameType::NoLimit,
+            ante: Ante::None,
+            straddle: Straddle::None,
+            format: Format::CashTable(buy_in),
+        };
+        let mut seat_changes = Vec::new();
+        let mut chips = (0, 0);
+        let (mut aborted, mut kicked) = (Vec::new(), Vec::new());
+        let callback = Seats {
+            cheater: None,
+            seats: vec![None; 3],
+            rounds: 0,
+            leave_after: vec![(0, 5), (1, 2), (2, 8), (3, 10)].into_iter().collect(),
+            top_ups: vec![(0, 500)].into_iter().collect(),
+            seat_changes: &mut seat_changes,
+            chips: &mut chips,
+            aborted: &mut aborted,
+            kicked: &mut kicked,
+        };
+
+        let (tx, rx) = channel();
+        for (player, buy_in) in vec![100, 20, 300, 100].into_iter().enumerate() {
+            tx.send((player, buy_in)).unwrap();
+        }
+        drop(tx);
+        let mut released = Vec::new();
+        CashTable::new(0, &request, buy_in, callback).play(&rx, |player| released.push(player));
+
+        // everybody eventually leaves, and the last player waits for a seat
+        released.sort();
+        assert_eq!(released, vec![0, 1, 2, 3]);
+        // buy-ins are kept within the limits
+        assert_eq!(&seat_changes[..3], &[(0, 100), (1, 50), (2, 200)]);
+        // topping up goes to at most the maximum buy-in
+        assert_eq!(seat_changes[3], (0, 200));
+        // the fourth player takes the first seat that comes free
+        assert_eq!(seat_changes[4], (1, 0));
+        assert_eq!(seat_changes[5], (1, 100));
+        // all chips are cashed out again, but for the odd chips the bank
+        // takes when splitting a pot
+        assert_eq!(chips.0, 100 + 50 + 200 + 100 + 100);
+        assert!(chips.1 <= chips.0 && chips.1 + 10 >= chips.0, "{:?}", chips);
+    }
+
+    #[test]
+    fn test_single_seat() {
+        let buy_in = BuyIn { min: 50, max: 200 };
+        let request = TableRequest {
+            n_players: 1,
+            small_blind: 1,
+            big_blind: 2,
+            stack: 100,
+            game_type: GameType::NoLimit,
+            ante: Ante::None,
+            straddle: Straddle::None,
+            format: Format::CashTable(buy_in),
+        };
+        let mut seat_changes = Vec::new();
+        let mut chips = (0, 0);
+        let (mut aborted, mut kicked) = (Vec::new(), Vec::new());
+        let callback = Seats {
+            cheater: None,
+            seats: vec![None; 1],
+            rounds: 0,
+            leave_after: vec![(0, 5)].into_iter().collect(),
+            top_ups: vec![(0, Money::MAX)].into_iter().collect(),
+            seat_changes: &mut seat_changes,
+            chips: &mut chips,
+            aborted: &mut aborted,
+            kicked: &mut kicked,
+        };
+
+        let (tx, rx) = channel();
+        tx.send((0, 100)).unwrap();
+        tx.send((1, 100)).unwrap();
+        let mut released = Vec::new();
+        CashTable::new(0, &request, buy_in, callback).play(&rx, |player| released.push(player));
+
+        // the table can never play, so the player leaves and the next one is
+        // not taken in
+        assert_eq!(released, vec![0]);
+        assert_eq!(rx.try_recv().unwrap(), (1, 100));
+        // a top up too big to add up is capped at the maximum buy-in
+        assert_eq!(seat_changes, vec![(0, 100), (0, 200), (0, 0)]);
+        assert_eq!(chips, (200, 200));
+    }
+
+    #[test]
+    fn test_kicked() {
+        let buy_in = BuyIn { min: 50, max: 200 };
+        let request = TableRequest {
+            n_players: 3,
+            small_blind: 1,
+            big_blind: 2,
+            stack: 100,
+            game_type: GameType::NoLimit,
+            ante: Ante::None,
+            straddle: Straddle::None,
+            format: Format::CashTable(buy_in),
+        };
+        let mut seat_changes = Vec::new();
+        let mut chips = (0, 0);
+        let (mut aborted, mut kicked) = (Vec::new(), Vec::new());
+        let callback = Seats {
+            cheater: Some(2),
+            seats: vec![None; 3],
+            rounds: 0,
+            leave_after: vec![(0, 2), (1, 2), (2, 10)].into_iter().collect(),
+            top_ups: HashMap::new(),
+            seat_changes: &mut seat_changes,
+            chips: &mut chips,
+            aborted: &mut aborted,
+            kicked: &mut kicked,
+        };
+
+        let (tx, rx) = channel();
+        for player in 0..3 {
+            tx.send((player, 100)).unwrap();
+        }
+        drop(tx);
+        let mut released = Vec::new();
+        CashTable::new(0, &request, buy_in, callback).play(&rx, |player| released.push(player));
+
+        // the table hears the round is undone, and the cheater is sent away
+        // with their chips instead of being released
+        assert_eq!(aborted, vec![vec![100, 100, 100]]);
+        assert_eq!(kicked, vec![2]);
+        released.sort();
+        assert_eq!(released, vec![0, 1]);
+        assert_eq!(seat_changes[3], (2, 0));
+        assert_eq!(chips.0, 300);
+    }
+}
@@ -0,0 +1,352 @@
+use std::io::{self, ErrorKind};
+use std::thread;
+use std::time::Duration;
+use websocket::client::ClientBuilder;
+
+use crate::communication::{
+    Event, Login, LoginError, LoginReply, Message, Money, PlayerAction, Reply, RequestTable,
+    Response,
+};
+use crate::protocol::{Capability, Frame, Protocol, EVENTS_VERSION, PROTOCOL_VERSION};
+use crate::state::GameState;
+use crate::transport::Transport;
+
+// how long to wait before the first attempt to reconnect, doubled after
+// every failed attempt
+const RECONNECT_DELAY: Duration = Duration::from_millis(500);
+
+/// A player that decides what to do at the table.
+pub trait Bot {
+    /// What to do when asked to act. The seat that is asked is `state.actor`.
+    fn act(&mut self, state: &GameState) -> PlayerAction;
+
+    /// Whether to take a voluntary straddle of the given amount.
+    fn straddle(&mut self, _state: &GameState, _amount: Money) -> bool {
+        false
+    }
+
+    /// Every message of the table, after the state took it in.
+    fn observe(&mut self, _state: &GameState, _event: &Event) {}
+
+    /// Whether to stop playing and disconnect.
+    fn stopping(&mut self) -> bool {
+        false
+    }
+}
+
+/// Any function of the state is a bot that never straddles.
+impl<F> Bot for F
+where
+    F: FnMut(&GameState) -> PlayerAction,
+{
+    fn act(&mut self, state: &GameState) -> PlayerAction {
+        self(state)
+    }
+}
+
+#[derive(Debug)]
+pub enum ClientError {
+    // the server could not be reached, or the connection broke for good
+    Connection(io::Error),
+    // the server did not let us log in
+    Rejected(LoginError),
+}
+
+/// A connection to a pokerroom server, logged in and ready to play. The
+/// state of the table is kept up to date from the messages the server sends.
+pub struct Client {
+    address: String,
+    login: Login,
+    connection: Box<dyn Transport>,
+    protocol: Protocol,
+    state: GameState,
+    // the table asked for last, sent again after reconnecting
+    request: Option<RequestTable>,
+    // how many times in a row to try to reconnect before giving up
+    retries: usize,
+}
+
+impl Client {
+    /// Connect to a server and log in.
+    /// address: the websocket address of the server, like ws://127.0.0.1:2794
+    pub fn connect(address: &str, name: &str, token: &str) -> Result<Client, ClientError> {
+        let login = Login {
+            name: String::from(name),
+            token: String::from(token),
+            version: PROTOCOL_VERSION,
+            capabilities: vec![Capability::ActionBroadcasts, Capability::StateSnapshots],
+        };
+        let (connection, protocol) = log_in(address, &login)?;
+        Ok(Client {
+            address: String::from(address),
+            login,
+            connection,
+            protocol,
+            state: GameState::new(Vec::new()),
+            request: None,
+            retries: 5,
+        })
+    }
+
+    /// Set how many times in a row the client tries to reconnect when the
+    /// connection breaks, 0 to give up right away.
+    pub fn retries(mut self, retries: usize) -> Client {
+        self.retries = retries;
+        self
+    }
+
+    /// The protocol the server agreed on.
+    pub fn protocol(&self) -> &Protocol {
+        &self.protocol
+    }
+
+    /// The state of the table the client plays at, or played at last.
+    pub fn state(&self) -> &GameState {
+        &self.state
+    }
+
+    /// Ask for a table: a new one, or one from the lobby listing.
+    pub fn join(&mut self, request: RequestTable) -> Result<(), ClientError> {
+        let frame = Frame::Text(serde_json::to_string(&request).unwrap());
+        self.request = Some(request);
+        self.connection.send(frame).map_err(ClientError::Connection)
+    }
+
+    /// Play at the table until the client is sent back to the lobby, or the
+    /// bot wants to stop. When the connection breaks, the client logs in
+    /// again and sends its last table request again. The server gives up the
+    /// seat of a player whose connection breaks, and cashes them out at a
+    /// cash table, so this only queues the client anew: the hand it was in
+    /// is lost, and it may get another seat or, when it asked for a new
+    /// table, another table.
+    pub fn play<B: Bot>(&mut self, bot: &mut B) -> Result<(), ClientError> {
+        let mut seated = false;
+        while !bot.stopping() {
+            let frame = match self.connection.recv() {
+                Ok(frame) => frame,
+                Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => {
+                    continue
+                }
+                Err(e) => {
+                    self.reconnect(e)?;
+                    seated = false;
+                    continue;
+                }
+            };
+            let event = match self.decode(&frame) {
+                Some(event) => event,
+                // listings of the lobby, which we are back in once seated
+                None if seated => return Ok(()),
+                None => continue,
+            };
+            seated = true;
+            if self.state.begins_hand(&event.message) {
+                self.state.start_hand(event.hand);
+            }
+            self.state.apply(&event.message);
+            bot.observe(&self.state, &event);
+            let response = match event.message {
+                Message::RequestAction { .. } => Response::Action(bot.act(&self.state)),
+                Message::RequestStraddle { amount, .. } => {
+                    Response::Straddle(bot.straddle(&self.state, amount))
+                }
+                _ => continue,
+            };
+            let frame = match self.protocol.version < EVENTS_VERSION {
+                true => serde_json::to_string(&response),
+                false => serde_json::to_string(&Reply {
+                    to: event.seq,
+                    response,
+                }),
+            };
+            let frame = Frame::Text(frame.unwrap());
+            if let Err(e) = self.connection.send(frame) {
+                self.reconnect(e)?;
+                seated = false;
+            }
+        }
+        self.connection.close();
+        Ok(())
+    }
+
+    /// Read a table message. Servers before version 3 send them without the
+    /// hand and sequence number, those are taken to be part of the current
+    /// hand.
+    fn decode(&self, frame: &Frame) -> Option<Event> {
+        match self.protocol.version < EVENTS_VERSION {
+            true => Some(Event {
+                hand: self.state.hand,
+                seq: 0,
+                message: frame.decode::<Message>()?,
+            }),
+            false => frame.decode::<Event>(),
+        }
+    }
+
+    /// Log in again after the connection broke, waiting longer after every
+    /// failed attempt, and send the last table request again.
+    /// error: why the connection broke, returned if reconnecting fails
+    fn reconnect(&mut self, error: io::Error) -> Result<(), ClientError> {
+        let mut delay = RECONNECT_DELAY;
+        let mut result = Err(ClientError::Connection(error));
+        for _ in 0..self.retries {
+            thread::sleep(delay);
+            delay *= 2;
+            result = log_in(&self.address, &self.login);
+            if result.is_ok() {
+                break;
+            }
+        }
+        let (connection, protocol) = result?;
+        self.connection = connection;
+        self.protocol = protocol;
+        // the snapshot sent when sitting down again rebuilds the state
+        self.state = GameState::new(Vec::new());
+        match self.request.clone() {
+            Some(request) => self.join(request),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Open a connection and log in.
+fn log_in(address: &str, login: &Login) -> Result<(Box<dyn Transport>, Protocol), ClientError> {
+    let mut connection = ClientBuilder::new(address)
+        .map_err(|e| ClientError::Connection(io::Error::new(ErrorKind::InvalidInput, e)))?
+        .connect_insecure()
+        .map_err(|e| ClientError::Connection(io::Error::other(e)))?;
+    connection
+        .send(Frame::Text(serde_json::to_string(login).unwrap()))
+        .map_err(ClientError::Connection)?;
+    let reply = connection
+        .recv()
+        .map_err(ClientError::Connection)?
+        .decode::<LoginReply>();
+    match reply {
+        Some(LoginReply::Accepted(protocol)) => Ok((Box::new(connection), protocol)),
+        Some(LoginReply::Welcome) => Ok((Box::new(connection), Protocol::default())),
+        Some(LoginReply::Rejected(error)) => Err(ClientError::Rejected(error)),
+        None => Err(ClientError::Connection(io::Error::new(
+            ErrorKind::InvalidData,
+            "the server did not answer the login",
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::communication::{Card, Lobby, Suit};
+    use std::net::TcpStream;
+    use websocket::sync::{Client as WsClient, Server};
+
+    /// Accept a connection and read its login.
+    fn accept(
+        server: &mut Server<websocket::server::NoTlsAcceptor>,
+    ) -> (WsClient<TcpStream>, Login) {
+        let mut client = server.accept().ok().unwrap().accept().ok().unwrap();
+        let login = client.recv().unwrap().decode::<Login>().unwrap();
+        (client, login)
+    }
+
+    fn send<T: serde::Serialize>(client: &mut WsClient<TcpStream>, message: &T) {
+        let text = serde_json::to_string(message).unwrap();
+        client.send(Frame::Text(text)).unwrap();
+    }
+
+    #[test]
+    fn test_play() {
+        let mut server = Server::bind("127.0.0.1:0").unwrap();
+        let address = format!("ws://{}", server.local_addr().unwrap());
+        let hole = (
+            Card {
+                rank: 14,
+                suit: Suit::Spades,
+            },
+            Card {
+                rank: 14,
+                suit: Suit::Hearts,
+            },
+        );
+        let request = RequestTable::Join {
+            table: 3,
+            buy_in: None,
+        };
+        let expected = request.clone();
+        let accepted = LoginReply::Accepted(Protocol::negotiate(3, &[]).unwrap());
+        let serving = thread::spawn(move || {
+            // the first connection breaks once the client asked for a table
+            let (mut client, login) = accept(&mut server);
+            assert_eq!(login.version, PROTOCOL_VERSION);
+            assert!(login.capabilities.contains(&Capability::StateSnapshots));
+            send(&mut client, &accepted);
+            send(&mut client, &Lobby { tables: vec![] });
+            let frame = client.recv().unwrap();
+            assert_eq!(frame.decode::<RequestTable>(), Some(expected.clone()));
+            drop(client);
+
+            // the client comes back and sends the same request
+            let (mut client, _) = accept(&mut server);
+            send(&mut client, &accepted);
+            send(&mut client, &Lobby { tables: vec![] });
+            let frame = client.recv().unwrap();
+            assert_eq!(frame.decode::<RequestTable>(), Some(expected));
+            let messages = vec![
+                Message::Players(vec![Some(String::from("bot")), Some(String::from("other"))]),
+                Message::Hole {
+                    player: 0,
+                    cards: hole,
+                },
+                Message::RequestAction {
+                    player: 0,
+                    bets: vec![Some(1), Some(2)],
+                    pot: 0,
+                    min_raise: Some(2),
+                },
+            ];
+            for (seq, message) in messages.into_iter().enumerate() {
+                send(
+                    &mut client,
+                    &Event {
+                        hand: 8,
+                        seq: seq as u64 + 10,
+                        message,
+                    },
+                );
+            }
+            let reply = client.recv().unwrap().decode::<Reply>().unwrap();
+            assert_eq!(reply.to, 12);
+            assert!(matches!(
+                reply.response,
+                Response::Action(PlayerAction::Raise(4))
+            ));
+            // back in the lobby
+            send(&mut client, &Lobby { tables: vec![] });
+
+            let (mut client, _) = accept(&mut server);
+            send(&mut client, &LoginReply::Rejected(LoginError::ServerFull));
+        });
+
+        let mut client = Client::connect(&address, "bot", "").unwrap().retries(2);
+        client.join(request).unwrap();
+        let mut asked = 0;
+        client
+            .play(&mut |state: &GameState| {
+                asked += 1;
+                assert_eq!(state.hand, 8);
+                assert_eq!(state.actor, Some(0));
+                assert_eq!(state.hole(), Some(hole));
+                assert_eq!(state.to_call(), 1);
+                PlayerAction::Raise(4)
+            })
+            .unwrap();
+        assert_eq!(asked, 1);
+        assert_eq!(client.state().seats[1].name.as_deref(), Some("other"));
+
+        match Client::connect(&address, "bot", "") {
+            Err(ClientError::Rejected(LoginError::ServerFull)) => {}
+            result => panic!("expected a rejection, got {:?}", result.err()),
+        }
+        serving.join().unwrap();
+    }
+}
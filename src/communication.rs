@@ -1,25 +1,191 @@
+use crate::protocol::{Capability, Protocol};
 use crate::score::Score;
 use num_derive::FromPrimitive;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 pub type Money = u32;
 
-#[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Clone, Copy, Debug)]
+/// The first message a client sends, before asking for a table.
+#[derive(Serialize, Deserialize, JsonSchema, PartialEq, Eq, Clone, Debug)]
+pub struct Login {
+    pub name: String,
+    pub token: String,
+    // the newest protocol version the client speaks, 1 if left out
+    #[serde(default = "first_version")]
+    pub version: u32,
+    // the optional features the client would like
+    #[serde(default)]
+    pub capabilities: Vec<Capability>,
+}
+
+fn first_version() -> u32 {
+    1
+}
+
+/// The answer of the server to a login. Rejected clients are disconnected.
+#[derive(Serialize, Deserialize, JsonSchema, PartialEq, Eq, Clone, Debug)]
+pub enum LoginReply {
+    // the client speaks version 1 of the protocol
+    Welcome,
+    // the protocol the server uses with a client that announced a newer
+    // version
+    Accepted(Protocol),
+    Rejected(LoginError),
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, PartialEq, Eq, Clone, Copy, Debug)]
+pub enum LoginError {
+    InvalidCredentials,
+    AlreadyLoggedIn,
+    // the server has as many players as it allows
+    ServerFull,
+    // the server does not speak the protocol version of the client
+    UnsupportedVersion,
+    // the client sent something else than a login
+    InvalidLogin,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, PartialEq, Eq, Hash, Clone, Debug)]
 pub enum RequestTable {
     RequestTable,
     Table(TableRequest),
+    // join a table from the lobby listing. Cash tables need a buy-in, which
+    // defaults to the minimum
+    Join {
+        table: usize,
+        #[serde(default)]
+        buy_in: Option<Money>,
+    },
+    // watch a running table without playing, receiving its public messages
+    // the given number of seconds late. Spectators of a multi-table
+    // tournament watch all of its tables
+    Watch {
+        table: usize,
+        #[serde(default)]
+        delay: u64,
+    },
+    // get the leaderboard, and stay in the lobby
+    Leaderboard,
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Clone, Copy, Debug)]
+/// The tables that are forming or running, sent to clients in the lobby
+/// whenever it changes.
+#[derive(Serialize, Deserialize, JsonSchema, PartialEq, Eq, Clone, Debug)]
+pub struct Lobby {
+    pub tables: Vec<TableInfo>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, PartialEq, Eq, Clone, Debug)]
+pub struct TableInfo {
+    pub id: usize,
+    pub request: TableRequest,
+    pub status: TableStatus,
+    // players waiting at a forming table, or playing at a running table
+    pub seated: usize,
+    pub seats: usize,
+    pub average_pot: Money,
+    pub hands_per_hour: u32,
+}
+
+/// All players that ever played, the highest rated first.
+#[derive(Serialize, Deserialize, JsonSchema, PartialEq, Clone, Debug)]
+pub struct Leaderboard {
+    pub players: Vec<Ranking>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, PartialEq, Clone, Debug)]
+pub struct Ranking {
+    pub name: String,
+    // Elo rating from the finished games and tournaments
+    pub rating: f64,
+    pub games: u64,
+    pub hands: u64,
+    // big blinds won per 100 hands, with the half width of its 95% confidence
+    // interval once enough hands are played
+    pub winrate: f64,
+    pub interval: Option<f64>,
+    pub bankroll: i64,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, PartialEq, Eq, Hash, Clone, Copy, Debug)]
+pub enum TableStatus {
+    // waiting for enough players to start, can be joined
+    Forming,
+    // playing, only cash tables can be joined
+    Running,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, PartialEq, Eq, Hash, Clone, Debug)]
 pub struct TableRequest {
     pub n_players: usize,
     pub small_blind: Money,
     pub big_blind: Money,
     pub stack: Money,
     pub game_type: GameType,
+    #[serde(default)]
+    pub ante: Ante,
+    #[serde(default)]
+    pub straddle: Straddle,
+    #[serde(default)]
+    pub format: Format,
+}
+
+/// How a table is run.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Hash, Clone, Default)]
+pub enum Format {
+    // when one player has all the chips, everybody restarts with their original stack
+    #[default]
+    Cash,
+    // a table with n_players seats that players sit down at and stand up from
+    // between rounds, bringing a stack within the buy-in limits
+    CashTable(BuyIn),
+    // play until one player has all the chips while the blinds go up, and
+    // rank the players by the order in which they bust
+    Tournament(BlindSchedule),
+    // a tournament spread over as many tables of the given size as needed,
+    // moving players as others bust to keep the tables balanced
+    MultiTable {
+        schedule: BlindSchedule,
+        table_size: usize,
+    },
+}
+
+/// The smallest and largest stack a player can bring to a cash table. Players
+/// can top up their stack to at most the maximum buy-in.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Hash, Clone, Copy)]
+pub struct BuyIn {
+    pub min: Money,
+    pub max: Money,
+}
+
+/// The blind levels of a tournament, which replace the blinds and ante of the
+/// table request.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Hash, Clone)]
+pub struct BlindSchedule {
+    pub levels: Vec<BlindLevel>,
+    pub level_up: LevelUp,
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Hash, Clone, Copy)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Hash, Clone, Copy)]
+pub struct BlindLevel {
+    pub small_blind: Money,
+    pub big_blind: Money,
+    #[serde(default)]
+    pub ante: Ante,
+}
+
+/// When a tournament moves to the next blind level. The last level is played
+/// until the tournament is over.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum LevelUp {
+    // after this many hands
+    Hands(u32),
+    // after this many seconds, checked between hands
+    Seconds(u64),
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Hash, Clone, Copy)]
 pub enum GameType {
     NoLimit,
     // TODO
@@ -27,14 +193,82 @@ pub enum GameType {
     // PotLimit,
 }
 
+/// Forced bets that go straight into the pot before the cards are dealt.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Hash, Clone, Copy, Default)]
+pub enum Ante {
+    #[default]
+    None,
+    // every player pays the ante
+    PerPlayer(Money),
+    // the big blind pays the given ante on behalf of the whole table
+    BigBlind(Money),
+}
+
+/// A blind of twice the big blind, posted by the player left of the big blind.
+/// The straddler acts last pre-flop and raises must be at least the straddle.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Hash, Clone, Copy, Default)]
+pub enum Straddle {
+    #[default]
+    None,
+    // the player is asked whether they want to straddle
+    Voluntary,
+    // the player always straddles
+    Mandatory,
+}
+
 /// the callback that is used to communicate the game state from the engine to
 /// the api.
 pub trait Callback {
     fn callback(&mut self, message: Message) -> Result<Response, Error>;
+
+    /// Whether the table should stop before the next round, because the
+    /// server is shutting down.
+    fn stopping(&mut self) -> bool {
+        false
+    }
+}
+
+/// A callback whose players can be moved to and from other tables between
+/// rounds.
+pub trait Seating: Callback {
+    type Connection;
+
+    /// Take the connection of the player in the given seat, leaving it empty.
+    fn leave(&mut self, seat: usize) -> Option<Self::Connection>;
+
+    /// Seat a player with the given stack in an empty seat.
+    fn join(&mut self, seat: usize, connection: Self::Connection, stack: Money);
+
+    /// The requests the player in the given seat sent outside of their turn,
+    /// which are handled between rounds.
+    fn requests(&mut self, _seat: usize) -> Vec<Response> {
+        Vec::new()
+    }
+
+    /// The player in the given seat brought chips to a cash table.
+    fn bought_in(&mut self, _seat: usize, _chips: Money) {}
+
+    /// The player in the given seat is about to leave a cash table with
+    /// their chips.
+    fn cashed_out(&mut self, _seat: usize, _chips: Money) {}
+
+    /// Send away a player that left their seat for making an illegal move,
+    /// instead of letting them play on elsewhere.
+    fn kick(&mut self, _connection: Self::Connection) {}
 }
 
 #[derive(
-    Debug, PartialEq, PartialOrd, Eq, Ord, Clone, Copy, FromPrimitive, Serialize, Deserialize,
+    Debug,
+    PartialEq,
+    PartialOrd,
+    Eq,
+    Ord,
+    Clone,
+    Copy,
+    FromPrimitive,
+    Serialize,
+    Deserialize,
+    JsonSchema,
 )]
 pub enum Suit {
     /*(♥)*/ Hearts,
@@ -45,23 +279,36 @@ pub enum Suit {
 
 /// Cards struct represents playing card.
 /// rank has range 2-14(aces high) but when evaluating straights includes 1(aces low).
-#[derive(Debug, PartialEq, PartialOrd, Eq, Ord, Clone, Copy, Serialize, Deserialize)]
+#[derive(
+    Debug, PartialEq, PartialOrd, Eq, Ord, Clone, Copy, Serialize, Deserialize, JsonSchema,
+)]
 pub struct Card {
     pub rank: u8,
     pub suit: Suit,
 }
 
 /// Response from the callback.
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub enum Response {
     // generic meaningless response
     Ack,
     // describes the player's move
     Action(PlayerAction),
+    // whether the player accepts a voluntary straddle
+    Straddle(bool),
+    // leave a cash table after the current round, can be sent at any time
+    StandUp,
+    // add chips to the stack at a cash table after the current round, up to
+    // the maximum buy-in. Can be sent at any time, also to rebuy after busting
+    // as long as the next round hasn't started
+    TopUp(Money),
+    // ask for a StateSnapshot of the table, can be sent at any time while
+    // seated
+    Snapshot,
 }
 
 /// Message sent to the callback
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
 pub enum Message {
     // game updates that require no response
     Hole {
@@ -71,31 +318,108 @@ pub enum Message {
     Flop(Card, Card, Card),
     River(Card),
     Turn(Card),
+    // a player posted a forced bet or made a decision. The amount is what
+    // they put in with it, the stack what they have left behind
+    PlayerActed {
+        player: usize,
+        action: Action,
+        amount: Money,
+        stack: Money,
+    },
+    // the end of a hand. The score and players are those of the main pot,
+    // the pot is the total of all pots
     Showdown {
         score: Score,
         pot: Money,
         players: Vec<usize>,
         stacks: Vec<Money>,
+        pots: Vec<Pot>,
     },
     GameOver,
+    // a tournament moved to a new blind level, the first level is 0
+    BlindsUp {
+        level: usize,
+        blinds: BlindLevel,
+    },
+    // a player busted out of a tournament and finished in the given place
+    Eliminated {
+        player: usize,
+        place: usize,
+    },
+    // the final ranking of a tournament, the winner first. In multi-table
+    // tournaments eliminations and standings are given by entrant number
+    Standings(Vec<usize>),
+    // the player got (re)seated at the given table and seat, either by a
+    // multi-table tournament or at a cash table. The entrant numbers the
+    // tournament entries or the players that sat down at the cash table
+    Seated {
+        table: usize,
+        player: usize,
+        entrant: usize,
+    },
+    // the names of the players in every seat of the table, None for empty
+    // seats. Sent whenever someone sits down or leaves
+    Players(Vec<Option<String>>),
+    // a player sat down, topped up or stood up (with an empty stack) at a cash
+    // table
+    SeatChange {
+        player: usize,
+        stack: Money,
+    },
     // inform player of current game state and request a PlayerAction response
     RequestAction {
         player: usize,
         bets: Vec<Option<Money>>,
         pot: Money,
+        // the smallest raise allowed, unless the player goes all-in for less.
+        // None when the player may not raise, because nobody is left to
+        // respond or a short all-in did not reopen the betting
+        min_raise: Option<Money>,
+    },
+    // offer the player left of the big blind to straddle, requires a
+    // Straddle response
+    RequestStraddle {
+        player: usize,
+        amount: Money,
+    },
+    // the server is shutting down and the table stopped after the last round,
+    // with the final stack of every seat
+    Shutdown {
+        stacks: Vec<Money>,
+    },
+    // the round that was being played is undone, these are the stacks from
+    // its start. Either something went wrong on the server and the table
+    // stopped, or a player at a cash table made an illegal move and is kicked
+    Aborted {
+        stacks: Vec<Money>,
     },
+    // the whole state of the table as the player may see it. Sent when the
+    // player asks for it, and to players with the StateSnapshots capability
+    // when they sit down and before every request to act
+    StateSnapshot(StateSnapshot),
     /// The offending player's id is passed as well so punishment can be served.
     Error(Error),
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// A pot and who won it. Besides the main pot there is a side pot for
+/// every amount a player went all-in for, which only the players that paid
+/// as much can win.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Clone)]
+pub struct Pot {
+    pub amount: Money,
+    // the players that split the pot
+    pub players: Vec<usize>,
+    pub score: Score,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
 pub struct Error {
     pub player: usize,
     pub error: ErrorMessage,
 }
 
 /// Everything that can go wrong and should be messaged to the players.
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Clone, Copy)]
 pub enum ErrorMessage {
     InvalidResponse,
     BetNotAllowed,
@@ -103,9 +427,97 @@ pub enum ErrorMessage {
 }
 
 /// All the actions at the disposal of the player.
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Clone, Copy)]
 pub enum PlayerAction {
     Fold,
     Call,
     Raise(Money),
 }
+
+/// A table message with the hand it belongs to and its place among the
+/// messages of the table. Clients of protocol version 3 and up get every
+/// table message wrapped in one.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct Event<M = Message> {
+    // the id of the hand, unique on the server. 0 before the first hand
+    pub hand: usize,
+    // counts up with every message of the table. Players only see the
+    // messages meant for them, so they miss some numbers
+    pub seq: u64,
+    pub message: M,
+}
+
+/// A response that names the message it answers by its sequence number. A
+/// reply to anything but the latest request to act is dropped as stale.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct Reply {
+    pub to: u64,
+    pub response: Response,
+}
+
+/// The state of a table at one moment, from the view of a single player.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Clone)]
+pub struct StateSnapshot {
+    // the seat the snapshot was made for
+    pub player: usize,
+    // the id of the hand, 0 before the first hand
+    pub hand: usize,
+    pub street: Street,
+    pub board: Vec<Card>,
+    // the chips in the pot, without the bets of the current street
+    pub pot: Money,
+    pub seats: Vec<SeatState>,
+    // the seat that is asked to act, if any
+    pub actor: Option<usize>,
+    // the smallest raise the actor may make, see Message::RequestAction
+    pub min_raise: Option<Money>,
+    // the hole cards of the player, None if they were not dealt in
+    pub hole: Option<(Card, Card)>,
+}
+
+/// A stage of a hand, named after the message that dealt its board cards.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Clone, Copy)]
+pub enum Street {
+    PreFlop,
+    Flop,
+    // the fourth board card
+    River,
+    // the fifth board card
+    Turn,
+    // the hand is over, or was undone
+    Finished,
+}
+
+/// A seat of a table as everybody can see it.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Clone)]
+pub struct SeatState {
+    // the player in the seat, None for empty seats
+    pub name: Option<String>,
+    pub stack: Money,
+    // what the player bet on the current street
+    pub bet: Money,
+    pub status: SeatStatus,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Clone, Copy)]
+pub enum SeatStatus {
+    Empty,
+    // seated, but not dealt in for lack of chips
+    Out,
+    Active,
+    Folded,
+    AllIn,
+}
+
+/// What a player did, as told to the whole table.
+#[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Clone, Copy)]
+pub enum Action {
+    Ante,
+    SmallBlind,
+    BigBlind,
+    Straddle,
+    Fold,
+    Check,
+    Call,
+    Raise,
+}
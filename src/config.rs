@@ -0,0 +1,387 @@
+use serde::Deserialize;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::communication::{Format, GameType, Money, TableRequest};
+
+/// The settings of a server, read from a TOML file. Everything has a default,
+/// so the file only needs to contain what differs from it.
+#[derive(Deserialize, PartialEq, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub address: String,
+    pub port: u16,
+    // also accept clients speaking line-delimited JSON over plain TCP on
+    // this port
+    pub tcp_port: Option<u16>,
+    // whether to serve the status page, and on which port
+    pub status: bool,
+    pub status_port: u16,
+    // the most players that can be logged in at once
+    pub max_connections: Option<usize>,
+    pub timeouts: Timeouts,
+    pub limits: Limits,
+    // tables that are always in the lobby. Forming tables open again as soon
+    // as they start, cash tables run from the start of the server
+    pub tables: Vec<TableRequest>,
+    // commands of bots to run when the server starts. They speak
+    // line-delimited JSON over their standard input and output, and log in
+    // like any other client
+    pub bots: Vec<Vec<String>>,
+    pub log: Logging,
+    // JSON file with the token of every player, anyone can log in without it
+    pub accounts: Option<PathBuf>,
+    // file to keep bankrolls and results in, kept in memory without it
+    pub ledger: Option<PathBuf>,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            address: String::from("127.0.0.1"),
+            port: 2794,
+            tcp_port: None,
+            status: true,
+            status_port: 2795,
+            max_connections: None,
+            timeouts: Timeouts::default(),
+            limits: Limits::default(),
+            tables: Vec::new(),
+            bots: Vec::new(),
+            log: Logging::default(),
+            accounts: None,
+            ledger: None,
+        }
+    }
+}
+
+/// How long clients get to answer, in seconds.
+#[derive(Deserialize, PartialEq, Eq, Clone, Copy, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Timeouts {
+    // to log in after connecting
+    pub login: u64,
+    // to respond when asked to act, players that take longer are kicked.
+    // None waits for as long as it takes
+    pub action: Option<u64>,
+}
+
+impl Default for Timeouts {
+    fn default() -> Timeouts {
+        Timeouts {
+            login: 30,
+            action: None,
+        }
+    }
+}
+
+/// The tables players can ask for. Predefined tables are always allowed.
+#[derive(Deserialize, PartialEq, Eq, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    pub game_types: Vec<GameType>,
+    pub min_big_blind: Money,
+    pub max_big_blind: Option<Money>,
+    // the most players at a table, or in a multi-table tournament
+    pub max_players: Option<usize>,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            game_types: vec![GameType::NoLimit],
+            min_big_blind: 0,
+            max_big_blind: None,
+            max_players: None,
+        }
+    }
+}
+
+impl Limits {
+    /// Whether a table can be asked for: it can be played, and its stakes are
+    /// within the limits. The stakes of a tournament are the blinds of its
+    /// levels.
+    pub fn allows(&self, request: &TableRequest) -> bool {
+        playable(request)
+            && self.game_types.contains(&request.game_type)
+            && blinds(request).iter().all(|&(_, big_blind)| {
+                big_blind >= self.min_big_blind
+                    && self.max_big_blind.is_none_or(|max| big_blind <= max)
+            })
+            && self.max_players.is_none_or(|max| request.n_players <= max)
+    }
+}
+
+/// The small and big blind of a table, or of every level of a tournament.
+fn blinds(request: &TableRequest) -> Vec<(Money, Money)> {
+    match &request.format {
+        Format::Tournament(schedule) | Format::MultiTable { schedule, .. } => schedule
+            .levels
+            .iter()
+            .map(|l| (l.small_blind, l.big_blind))
+            .collect(),
+        _ => vec![(request.small_blind, request.big_blind)],
+    }
+}
+
+/// Whether a table can be played at all. It needs at least two players, a
+/// big blind at least as big as the small blind, a tournament needs blind
+/// levels and tables of at least two seats, and a cash table a buy-in range
+/// that is not empty.
+fn playable(request: &TableRequest) -> bool {
+    let format = match &request.format {
+        Format::Tournament(schedule) => !schedule.levels.is_empty(),
+        Format::MultiTable {
+            schedule,
+            table_size,
+        } => !schedule.levels.is_empty() && *table_size >= 2,
+        Format::CashTable(buy_in) => buy_in.min <= buy_in.max,
+        Format::Cash => true,
+    };
+    format
+        && request.n_players >= 2
+        && blinds(request)
+            .iter()
+            .all(|&(small_blind, big_blind)| big_blind > 0 && small_blind <= big_blind)
+}
+
+/// Where the server writes what happens.
+#[derive(Deserialize, PartialEq, Eq, Clone, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Logging {
+    // write nothing at all
+    pub quiet: bool,
+    // append to this file instead of writing to stdout
+    pub file: Option<PathBuf>,
+}
+
+impl Config {
+    /// Read the configuration from a TOML file. Predefined tables that can
+    /// not be played are an error.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Config> {
+        Config::parse(&fs::read_to_string(path)?)
+    }
+
+    fn parse(text: &str) -> io::Result<Config> {
+        let config: Config =
+            toml::from_str(text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        if let Some(table) = config.tables.iter().find(|table| !playable(table)) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("table {:?} cannot be played", table),
+            ));
+        }
+        Ok(config)
+    }
+
+    pub fn address(&self) -> String {
+        format!("{}:{}", self.address, self.port)
+    }
+
+    /// The address for plain TCP clients, None if they are not accepted.
+    pub fn tcp_address(&self) -> Option<String> {
+        self.tcp_port
+            .map(|port| format!("{}:{}", self.address, port))
+    }
+
+    /// Whether players can ask for a table: it is predefined, or within the
+    /// limits. Predefined cash tables can be joined with any buy-in.
+    pub fn allows(&self, request: &TableRequest) -> bool {
+        self.limits.allows(request)
+            || self.tables.iter().any(|table| match table.format {
+                Format::CashTable(_) => {
+                    *request
+                        == TableRequest {
+                            stack: request.stack,
+                            ..table.clone()
+                        }
+                }
+                _ => request == table,
+            })
+    }
+
+    /// The address of the status page, None if it is not served.
+    pub fn status_address(&self) -> Option<String> {
+        if self.status {
+            Some(format!("{}:{}", self.address, self.status_port))
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::communication::{Ante, BlindLevel, BlindSchedule, BuyIn, LevelUp, Straddle};
+
+    #[test]
+    fn test_example_config() {
+        let config = Config::parse(include_str!("../server.example.toml")).unwrap();
+        assert_eq!(config.address(), "127.0.0.1:2794");
+        assert_eq!(config.status_address().unwrap(), "127.0.0.1:2795");
+        assert_eq!(config.tcp_address().unwrap(), "127.0.0.1:2796");
+        assert!(config.bots.is_empty());
+        assert_eq!(config.max_connections, Some(100));
+        assert_eq!(config.timeouts.action, Some(10));
+        assert_eq!(config.limits.max_big_blind, Some(200));
+        assert_eq!(config.tables.len(), 3);
+        assert_eq!(
+            config.tables[0].format,
+            Format::CashTable(BuyIn { min: 40, max: 200 })
+        );
+        assert_eq!(config.tables[1].ante, Ante::PerPlayer(1));
+        match &config.tables[2].format {
+            Format::Tournament(schedule) => {
+                assert_eq!(schedule.level_up, LevelUp::Hands(10));
+                assert_eq!(schedule.levels.len(), 3);
+            }
+            format => panic!("not a tournament: {:?}", format),
+        }
+        // "Cash" keeps meaning the table that restarts with the original stacks
+        let format = serde_json::from_str::<Format>(r#""Cash""#).unwrap();
+        assert_eq!(format, Format::Cash);
+        let format = r#"{"CashTable":{"min":40,"max":200}}"#;
+        assert_eq!(
+            serde_json::from_str::<Format>(format).unwrap(),
+            config.tables[0].format
+        );
+
+        // an empty file is the default configuration
+        assert_eq!(toml::from_str::<Config>("").unwrap(), Config::default());
+        assert_eq!(Config::default().tcp_address(), None);
+        let config: Config = toml::from_str(r#"bots = [["./bot", "--fold"]]"#).unwrap();
+        assert_eq!(config.bots, vec![vec!["./bot", "--fold"]]);
+        assert!(toml::from_str::<Config>("prot = 1").is_err());
+
+        // predefined tables have to be playable
+        let table = |extra: &str| {
+            format!(
+                "[[tables]]\nn_players = 2\nsmall_blind = 1\nbig_blind = 2\nstack = 100\n\
+                 game_type = \"NoLimit\"\n{}",
+                extra
+            )
+        };
+        // (table, valid)
+        let cases = vec![
+            (table(""), true),
+            (
+                table("format = { CashTable = { min = 200, max = 40 } }"),
+                false,
+            ),
+            (
+                table("format = { Tournament = { levels = [], level_up = { Hands = 10 } } }"),
+                false,
+            ),
+            (
+                table(
+                    "[tables.format.MultiTable]\ntable_size = 0\n\
+                     schedule = { levels = [{ small_blind = 1, big_blind = 2 }], \
+                     level_up = { Hands = 10 } }",
+                ),
+                false,
+            ),
+            (table("").replace("big_blind = 2", "big_blind = 0"), false),
+            (table("").replace("n_players = 2", "n_players = 1"), false),
+        ];
+        for (text, valid) in cases {
+            let error = Config::parse(&text).err();
+            assert_eq!(error.is_none(), valid, "{}", text);
+            if let Some(error) = error {
+                assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+                assert!(error.to_string().contains("cannot be played"), "{}", error);
+            }
+        }
+    }
+
+    #[test]
+    fn test_limits() {
+        let limits = Limits {
+            min_big_blind: 2,
+            max_big_blind: Some(100),
+            max_players: Some(6),
+            ..Limits::default()
+        };
+        let request = |big_blind, n_players, format| TableRequest {
+            n_players,
+            small_blind: big_blind / 2,
+            big_blind,
+            stack: 1000,
+            game_type: GameType::NoLimit,
+            ante: Ante::None,
+            straddle: Straddle::None,
+            format,
+        };
+        let tournament = |big_blinds: &[Money]| {
+            Format::Tournament(BlindSchedule {
+                levels: big_blinds
+                    .iter()
+                    .map(|&big_blind| BlindLevel {
+                        small_blind: big_blind / 2,
+                        big_blind,
+                        ante: Ante::None,
+                    })
+                    .collect(),
+                level_up: LevelUp::Hands(10),
+            })
+        };
+        // (request, allowed)
+        let cases = vec![
+            (request(2, 6, Format::Cash), true),
+            (request(100, 2, Format::Cash), true),
+            (request(1, 2, Format::Cash), false),
+            (request(200, 2, Format::Cash), false),
+            (request(2, 7, Format::Cash), false),
+            (request(2, 2, tournament(&[2, 4, 100])), true),
+            (request(2, 2, tournament(&[2, 4, 200])), false),
+        ];
+        for (request, allowed) in cases {
+            assert_eq!(limits.allows(&request), allowed, "{:?}", request);
+        }
+
+        // tables that cannot be played, whatever the limits
+        let defaults = Limits::default();
+        // (request, allowed)
+        let cases = vec![
+            (request(2, 1, Format::Cash), false),
+            (request(0, 2, Format::Cash), false),
+            (
+                TableRequest {
+                    small_blind: 3,
+                    ..request(2, 2, Format::Cash)
+                },
+                false,
+            ),
+            (request(2, 2, tournament(&[2, 0])), false),
+            (request(2, 2, tournament(&[])), false),
+            (
+                request(2, 2, Format::CashTable(BuyIn { min: 200, max: 40 })),
+                false,
+            ),
+            (request(2, 2, Format::Cash), true),
+        ];
+        for (request, allowed) in cases {
+            assert_eq!(defaults.allows(&request), allowed, "{:?}", request);
+        }
+        assert!(!Limits {
+            game_types: Vec::new(),
+            ..Limits::default()
+        }
+        .allows(&request(2, 2, Format::Cash)));
+
+        // predefined tables are allowed whatever the limits
+        let cash = Format::CashTable(BuyIn { min: 100, max: 500 });
+        let config = Config {
+            limits,
+            tables: vec![request(200, 2, Format::Cash), request(200, 9, cash.clone())],
+            ..Config::default()
+        };
+        assert!(config.allows(&request(200, 2, Format::Cash)));
+        assert!(!config.allows(&request(400, 2, Format::Cash)));
+        assert!(config.allows(&TableRequest {
+            stack: 300,
+            ..request(200, 9, cash)
+        }));
+    }
+}
@@ -1,9 +1,13 @@
 use num_traits::FromPrimitive;
 use rand::seq::SliceRandom;
 use rand::thread_rng;
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use std::time::Instant;
 
 use crate::communication::{
-    Callback, Card, Error, ErrorMessage, GameType, Message, Money, PlayerAction, Response, Suit,
+    Action, Ante, BlindLevel, BlindSchedule, Callback, Card, Error, ErrorMessage, GameType,
+    LevelUp, Message, Money, PlayerAction, Pot, Response, Straddle, Suit,
 };
 use crate::score::Score;
 
@@ -22,7 +26,10 @@ where
     game_type: GameType,
     small_blind: Money,
     big_blind: Money,
-    dealer: usize,
+    ante: Ante,
+    straddle: Straddle,
+    // positions of the previous round, None before the first round
+    positions: Option<Positions>,
     players: Vec<Player>,
     callback: T,
 }
@@ -37,6 +44,8 @@ where
         game_type: GameType,
         small_blind: Money,
         big_blind: Money,
+        ante: Ante,
+        straddle: Straddle,
         players: Vec<Money>,
         callback: T,
     ) -> Table<T> {
@@ -45,41 +54,188 @@ where
             game_type,
             small_blind,
             big_blind,
-            dealer: 0,
+            ante,
+            straddle,
+            positions: None,
             players,
             callback,
         }
     }
 
-    /// Play indefinitely. Only break on errors
-    pub fn play(mut self) {
+    /// Play indefinitely. Only break on errors or when the callback asks to
+    /// stop.
+    /// Returns: false if the table was aborted because a round panicked
+    pub fn play(mut self) -> bool {
         let players = self.players.clone();
-        loop {
+        while !self.stopped() {
             if self.players.iter().filter(|p| p.active()).count() == 1 {
                 self.callback.callback(Message::GameOver).ok();
                 self.players = players.clone();
+                self.positions = None;
+            }
+            match self.play_round_isolated() {
+                Some(Ok(())) => {}
+                Some(Err(e)) => {
+                    self.callback.callback(Message::Error(e)).ok();
+                    break;
+                }
+                None => return false,
+            }
+        }
+        true
+    }
+
+    /// Play a tournament until one player has all the chips, raising the
+    /// blinds according to the schedule. Players that bust in the same round
+    /// are ranked by the stack they started the round with. Stops early on
+    /// errors, or when the callback asks to stop.
+    /// Returns: false if the table was aborted because a round panicked
+    pub fn play_tournament(mut self, schedule: &BlindSchedule) -> bool {
+        let start = Instant::now();
+        let mut hands = 0;
+        let mut level = 0;
+        // players in the order in which they busted
+        let mut busted = Vec::new();
+        if let Some(blinds) = schedule.levels.first() {
+            if self.raise_blinds(level, *blinds).is_err() {
+                return true;
             }
-            if let Err(e) = self.play_round() {
-                self.callback.callback(Message::Error(e)).ok();
-                break;
+        }
+
+        while self.players.iter().filter(|p| p.active()).count() > 1 {
+            if self.stopped() {
+                return true;
             }
+            let next_level = blind_level(schedule, hands, start);
+            if next_level > level {
+                level = next_level;
+                if self.raise_blinds(level, schedule.levels[level]).is_err() {
+                    return true;
+                }
+            }
+
+            let stacks = self.players.iter().map(|p| p.stack).collect::<Vec<_>>();
+            match self.play_round_isolated() {
+                Some(Ok(())) => {}
+                Some(Err(e)) => {
+                    self.callback.callback(Message::Error(e)).ok();
+                    return true;
+                }
+                None => return false,
+            }
+            hands += 1;
+
+            let mut bust_now = (0..stacks.len())
+                .filter(|&i| stacks[i] > ZERO_MONEY && !self.players[i].active())
+                .collect::<Vec<_>>();
+            bust_now.sort_by_key(|&i| stacks[i]);
+            let remaining = stacks.iter().filter(|&&s| s > ZERO_MONEY).count();
+            for (i, &player) in bust_now.iter().enumerate() {
+                let place = remaining - i;
+                if self
+                    .callback
+                    .callback(Message::Eliminated { player, place })
+                    .is_err()
+                {
+                    return true;
+                }
+            }
+            busted.extend(bust_now);
+        }
+
+        let mut standings = (0..self.players.len())
+            .filter(|&i| self.players[i].active())
+            .collect::<Vec<_>>();
+        standings.extend(busted.iter().rev());
+        self.callback.callback(Message::Standings(standings)).ok();
+        true
+    }
+
+    /// Move to a new blind level and inform the players.
+    pub(crate) fn raise_blinds(
+        &mut self,
+        level: usize,
+        blinds: BlindLevel,
+    ) -> Result<Response, Error> {
+        self.small_blind = blinds.small_blind;
+        self.big_blind = blinds.big_blind;
+        self.ante = blinds.ante;
+        self.callback.callback(Message::BlindsUp { level, blinds })
+    }
+
+    /// Whether the callback asks the table to stop before the next round. If
+    /// so, the players are told their final stacks.
+    pub(crate) fn stopped(&mut self) -> bool {
+        if !self.callback.stopping() {
+            return false;
+        }
+        let stacks = self.stacks();
+        self.callback.callback(Message::Shutdown { stacks }).ok();
+        true
+    }
+
+    /// The stack of every seat, empty seats have no chips.
+    pub(crate) fn stacks(&self) -> Vec<Money> {
+        self.players.iter().map(|p| p.stack + p.bet).collect()
+    }
+
+    /// Change the stack of a seat between rounds, or seat a new player by
+    /// giving an empty seat chips. Bets of an aborted round are dropped.
+    pub(crate) fn set_stack(&mut self, seat: usize, stack: Money) {
+        self.players[seat] = Player::new(stack);
+    }
+
+    /// The seat that posts the big blind next round.
+    pub(crate) fn next_big_blind(&self) -> usize {
+        self.next_positions().big_blind
+    }
+
+    pub(crate) fn callback_mut(&mut self) -> &mut T {
+        &mut self.callback
+    }
+
+    /// Play a single round like `play_round`, but survive a panic in the
+    /// engine or the callback: the state of the hand is logged, the stacks
+    /// are restored to what they were at the start of the round and the
+    /// players are told the table is aborted.
+    /// Returns: the result of the round, None if it panicked
+    pub(crate) fn play_round_isolated(&mut self) -> Option<Result<(), Error>> {
+        let stacks = self.stacks();
+        let panic = match panic::catch_unwind(AssertUnwindSafe(|| self.play_round())) {
+            Ok(result) => return Some(result),
+            Err(panic) => panic,
+        };
+        log!(
+            "a round panicked: {}. Blinds {}/{}, positions {:?}, players {:?}, stacks at the start {:?}",
+            panic_message(&panic),
+            self.small_blind,
+            self.big_blind,
+            self.positions,
+            self.players,
+            stacks
+        );
+        for (seat, &stack) in stacks.iter().enumerate() {
+            self.set_stack(seat, stack);
         }
+        self.abort();
+        None
+    }
+
+    /// Tell the players the table is aborted, with their current stacks.
+    pub(crate) fn abort(&mut self) {
+        let stacks = self.stacks();
+        self.callback.callback(Message::Aborted { stacks }).ok();
     }
 
     /// Play a single round.
-    fn play_round(&mut self) -> Result<(), Error> {
+    pub(crate) fn play_round(&mut self) -> Result<(), Error> {
         let mut deck = Deck::new();
         let mut pot = ZERO_MONEY;
         let mut table_cards = Vec::new();
-        let n = self.players.len();
+        let positions = self.next_positions();
 
         for (i, player) in &mut self.players.iter_mut().enumerate() {
-            // set the small & big blind
-            match (i + n - self.dealer) % n {
-                1 if player.active() => player.call(self.small_blind),
-                2 if player.active() => player.call(self.big_blind),
-                _ => {}
-            }
+            player.paid = ZERO_MONEY;
             player.hole_cards = if player.active() {
                 let cards = (deck.draw(), deck.draw());
                 self.callback.callback(Message::Hole { player: i, cards })?;
@@ -88,8 +244,11 @@ where
                 None
             }
         }
+        pot += self.post_antes(&positions)?;
+        let (first_player, min_raise) = self.post_blinds(&positions)?;
+
         // pre-flop
-        pot += self.betting_round((self.dealer + 3) % n, pot)?;
+        pot += self.betting_round(first_player, min_raise, pot)?;
         for _ in 0..3 {
             table_cards.push(deck.draw());
         }
@@ -100,75 +259,214 @@ where
         ))?;
 
         //  river
-        pot += self.betting_round(self.dealer + 1, pot)?;
+        pot += self.betting_round(positions.dealer + 1, self.big_blind, pot)?;
         table_cards.push(deck.draw());
         self.callback.callback(Message::River(table_cards[3]))?;
 
         //  turn
-        pot += self.betting_round(self.dealer + 1, pot)?;
+        pot += self.betting_round(positions.dealer + 1, self.big_blind, pot)?;
         table_cards.push(deck.draw());
         self.callback.callback(Message::Turn(table_cards[4]))?;
 
         // showdown
-        pot += self.betting_round(self.dealer + 1, pot)?;
-        let (splitters, score) = self.showdown(&table_cards);
-
-        //  divide pot over winners, bank takes change via integer division
-        let share = pot / splitters.len() as Money;
-        for splitter in &splitters {
-            self.players[*splitter].stack += share;
-        }
+        pot += self.betting_round(positions.dealer + 1, self.big_blind, pot)?;
+        let pots = self.showdown(&table_cards);
         self.callback.callback(Message::Showdown {
-            score,
+            score: pots[0].score,
             pot,
-            players: splitters,
+            players: pots[0].players.clone(),
             stacks: self.players.iter().map(|p| p.stack).collect(),
+            pots,
         })?;
-        self.dealer = (self.dealer + 1) % self.players.len();
+        self.positions = Some(positions);
         Ok(())
     }
 
-    /// A single round of poker consists of a series of betting rounds.
-    /// These rules depend on the game type.
-    /// Returns: the combined size of all table bets or an error if a
-    /// player made an illegal move
-    fn betting_round(&mut self, first_player: usize, pot: Money) -> Result<Money, Error> {
-        let mut can_bet_count = self.players.iter().filter(|p| p.can_bet()).count();
+    /// Determine the button and blinds for the next round. The big blind moves
+    /// to the next active player and the small blind and button follow in the
+    /// seats the blinds occupied last round, so nobody skips or repeats a
+    /// blind when players bust. This can leave the small blind or the button
+    /// on an empty seat, in which case no small blind is posted. Heads-up the
+    /// button posts the small blind, so it acts first pre-flop and last after
+    /// the flop.
+    fn next_positions(&self) -> Positions {
         let n = self.players.len();
-        let mut current_bet = self.players.iter().map(|p| p.bet).max().unwrap();
-        let mut min_betsize = self.big_blind;
-        for i in (0..self.players.len()).map(|i| (i + first_player) % n) {
-            if can_bet_count < 2 {
-                break;
+        let next_active = |seat: usize| {
+            (1..=n)
+                .map(|i| (seat + i) % n)
+                .find(|&i| self.players[i].active())
+                .unwrap()
+        };
+        let heads_up = self.players.iter().filter(|p| p.active()).count() == 2;
+
+        match self.positions {
+            None => {
+                let dealer = next_active(n - 1);
+                let small_blind = if heads_up {
+                    dealer
+                } else {
+                    next_active(dealer)
+                };
+                Positions {
+                    dealer,
+                    small_blind,
+                    big_blind: next_active(small_blind),
+                }
             }
-            if self.players[i].can_bet() {
-                let raise = self.bet(i, current_bet, min_betsize, pot)?;
-                min_betsize = min_betsize.max(raise);
-                current_bet += raise;
-                if !self.players[i].can_bet() {
-                    can_bet_count -= 1;
+            Some(previous) if heads_up => {
+                let big_blind = next_active(previous.big_blind);
+                let dealer = next_active(big_blind);
+                Positions {
+                    dealer,
+                    small_blind: dealer,
+                    big_blind,
                 }
             }
+            Some(previous) => Positions {
+                dealer: previous.small_blind,
+                small_blind: previous.big_blind,
+                big_blind: next_active(previous.big_blind),
+            },
         }
+    }
 
-        // continue until everyone equal, all-in or folded
-        let mut previous_bet = ZERO_MONEY;
+    /// Collect the antes of all players in the round, which go straight into
+    /// the pot. A short stacked big blind pays the blind before the big blind
+    /// ante.
+    /// Returns: the total of all antes
+    fn post_antes(&mut self, positions: &Positions) -> Result<Money, Error> {
+        let antes = match self.ante {
+            Ante::None => Vec::new(),
+            Ante::PerPlayer(ante) => (0..self.players.len())
+                .filter(|&i| !self.players[i].folded())
+                .map(|i| (i, ante))
+                .collect(),
+            Ante::BigBlind(ante) => {
+                let stack = self.players[positions.big_blind].stack;
+                vec![(
+                    positions.big_blind,
+                    ante.min(stack.saturating_sub(self.big_blind)),
+                )]
+            }
+        };
+        let mut paid = ZERO_MONEY;
+        for (player, ante) in antes {
+            let stack = self.players[player].stack;
+            paid += self.players[player].ante(ante);
+            self.acted(player, Action::Ante, stack)?;
+        }
+        Ok(paid)
+    }
 
-        while previous_bet < current_bet {
-            previous_bet = current_bet;
-            for i in (0..self.players.len()).map(|i| (i + first_player) % n) {
-                if can_bet_count < 2 {
-                    break;
+    /// Tell the table what a player did.
+    /// stack: the stack of the player before they acted
+    fn acted(&mut self, player: usize, action: Action, stack: Money) -> Result<(), Error> {
+        let left = self.players[player].stack;
+        self.callback.callback(Message::PlayerActed {
+            player,
+            action,
+            amount: stack - left,
+            stack: left,
+        })?;
+        Ok(())
+    }
+
+    /// Post the small and big blind, and the straddle if the table has one.
+    /// Straddles are not played heads-up.
+    /// Returns: the first player to act pre-flop and the minimum raise
+    fn post_blinds(&mut self, positions: &Positions) -> Result<(usize, Money), Error> {
+        if !self.players[positions.small_blind].folded() {
+            let stack = self.players[positions.small_blind].stack;
+            self.players[positions.small_blind].call(self.small_blind);
+            self.acted(positions.small_blind, Action::SmallBlind, stack)?;
+        }
+        let stack = self.players[positions.big_blind].stack;
+        self.players[positions.big_blind].call(self.big_blind);
+        self.acted(positions.big_blind, Action::BigBlind, stack)?;
+
+        let n = self.players.len();
+        let in_round = self.players.iter().filter(|p| !p.folded()).count();
+        let straddler = (1..n)
+            .map(|i| (positions.big_blind + i) % n)
+            .find(|&i| !self.players[i].folded())
+            .unwrap_or(positions.big_blind);
+        let amount = 2 * self.big_blind;
+        let straddles = in_round > 2
+            && self.players[straddler].can_bet()
+            && match self.straddle {
+                Straddle::None => false,
+                Straddle::Mandatory => true,
+                Straddle::Voluntary => {
+                    self.players[straddler].stack >= amount
+                        && match self.callback.callback(Message::RequestStraddle {
+                            player: straddler,
+                            amount,
+                        })? {
+                            Response::Straddle(straddles) => straddles,
+                            _ => {
+                                return Err(Error {
+                                    player: straddler,
+                                    error: ErrorMessage::InvalidResponse,
+                                })
+                            }
+                        }
                 }
-                if self.players[i].can_bet() && self.players[i].bet != current_bet {
-                    let raise = self.bet(i, current_bet, min_betsize, pot)?;
-                    min_betsize = min_betsize.max(raise);
-                    current_bet += raise;
-                    if !self.players[i].can_bet() {
-                        can_bet_count -= 1;
-                    }
+            };
+
+        if straddles {
+            let stack = self.players[straddler].stack;
+            self.players[straddler].call(amount);
+            self.acted(straddler, Action::Straddle, stack)?;
+            Ok((straddler + 1, amount))
+        } else {
+            Ok((positions.big_blind + 1, self.big_blind))
+        }
+    }
+
+    /// A single round of poker consists of a series of betting rounds.
+    /// These rules depend on the game type.
+    /// Action goes around the table until every player who can still bet has
+    /// acted and matched the highest bet. A raise smaller than the previous
+    /// full raise (only possible when going all-in) does not reopen the
+    /// betting for players who already acted, unless the raises they face add
+    /// up to a full raise.
+    /// Returns: the combined size of all table bets or an error if a
+    /// player made an illegal move
+    fn betting_round(
+        &mut self,
+        first_player: usize,
+        mut min_raise: Money,
+        pot: Money,
+    ) -> Result<Money, Error> {
+        let n = self.players.len();
+        let mut current_bet = self.players.iter().map(|p| p.bet).max().unwrap();
+        // the bet each player faced when they last acted, None if they didn't
+        let mut acted_on: Vec<Option<Money>> = vec![None; n];
+        // number of consecutive players that had no decision to make
+        let mut idle = 0;
+        let mut i = first_player % n;
+
+        while idle < n && self.players.iter().filter(|p| !p.folded()).count() > 1 {
+            let others_can_bet = (0..n)
+                .filter(|&j| j != i && self.players[j].can_bet())
+                .count()
+                > 0;
+            let player = &self.players[i];
+            if player.can_bet()
+                && (player.bet < current_bet || (acted_on[i].is_none() && others_can_bet))
+            {
+                let can_raise =
+                    others_can_bet && acted_on[i].is_none_or(|bet| current_bet - bet >= min_raise);
+                let raise = self.bet(i, current_bet, min_raise, can_raise, pot)?;
+                if raise >= min_raise {
+                    min_raise = raise;
                 }
+                current_bet += raise;
+                acted_on[i] = Some(current_bet);
+                idle = 0;
             }
+            idle += 1;
+            i = (i + 1) % n;
         }
 
         Ok(self
@@ -180,13 +478,16 @@ where
 
     /// Request a player's action, verify this action is allowed within the
     /// rule set of the current game type, and update pot & table bets.
+    /// Returns: the amount by which the player raised the highest bet.
     fn bet(
         &mut self,
         player: usize,
         max_bet: Money,
-        min_betsize: Money,
+        min_raise: Money,
+        can_raise: bool,
         pot: Money,
     ) -> Result<Money, Error> {
+        let stack = self.players[player].stack;
         match self.callback.callback(Message::RequestAction {
             player,
             bets: self
@@ -195,23 +496,41 @@ where
                 .map(|p| if p.folded() { None } else { Some(p.bet) })
                 .collect(),
             pot,
+            min_raise: Some(min_raise).filter(|_| can_raise),
         }) {
             Ok(Response::Action(PlayerAction::Fold)) => {
                 self.players[player].fold();
+                self.acted(player, Action::Fold, stack)?;
                 Ok(ZERO_MONEY)
             }
             Ok(Response::Action(PlayerAction::Call)) => {
+                let action = if self.players[player].bet == max_bet {
+                    Action::Check
+                } else {
+                    Action::Call
+                };
                 self.players[player].call(max_bet);
+                self.acted(player, action, stack)?;
                 Ok(ZERO_MONEY)
             }
             Ok(Response::Action(PlayerAction::Raise(raise))) => {
-                if raise < min_betsize || self.players[player].raise(max_bet + raise).is_err() {
+                // a raise too big to add up is not allowed either
+                let allowed = match max_bet.checked_add(raise) {
+                    Some(bet) => {
+                        can_raise
+                            && self.raise_allowed(player, bet, raise >= min_raise)
+                            && self.players[player].raise(bet).is_ok()
+                    }
+                    None => false,
+                };
+                if allowed {
+                    self.acted(player, Action::Raise, stack)?;
+                    Ok(raise)
+                } else {
                     Err(Error {
                         player,
                         error: ErrorMessage::BetNotAllowed,
                     })
-                } else {
-                    Ok(raise)
                 }
             }
             Ok(_) => Err(Error {
@@ -222,9 +541,20 @@ where
         }
     }
 
-    /// Calculate the score of each player, determine the winning hand and the
-    /// winners.
-    fn showdown(&self, table_cards: &[Card]) -> (Vec<usize>, Score) {
+    /// Whether a raise to `bet` is a legal size. A raise that is not a full
+    /// raise is only allowed when it puts the player all-in.
+    fn raise_allowed(&self, player: usize, bet: Money, full_raise: bool) -> bool {
+        match self.game_type {
+            GameType::NoLimit => full_raise || bet == self.players[player].all_in(),
+        }
+    }
+
+    /// Calculate the score of each player and divide the pot. Every amount a
+    /// player that is still in paid in total makes a pot, which the best
+    /// hands among the players that paid at least as much split. The bank
+    /// takes the change of uneven splits via integer division.
+    /// Returns: the pots, the main pot first
+    fn showdown(&mut self, table_cards: &[Card]) -> Vec<Pot> {
         let scores = self
             .players
             .iter()
@@ -241,23 +571,71 @@ where
                 ]),
             })
             .collect::<Vec<Score>>();
-        let max_score = scores.iter().max().unwrap();
-        let splitters = scores
+        let mut levels = self
+            .players
             .iter()
-            .enumerate()
-            .filter_map(|(i, s)| if s == max_score { Some(i) } else { None })
-            .collect();
+            .filter(|p| !p.folded())
+            .map(|p| p.paid)
+            .collect::<Vec<_>>();
+        levels.sort();
+        levels.dedup();
 
-        (splitters, *max_score)
+        let mut pots = Vec::new();
+        let mut previous = ZERO_MONEY;
+        for (i, &level) in levels.iter().enumerate() {
+            // what is left of the bets of folded players goes in the last pot
+            let last = i + 1 == levels.len();
+            let amount = self
+                .players
+                .iter()
+                .map(|p| if last { p.paid } else { p.paid.min(level) } - p.paid.min(previous))
+                .sum::<Money>();
+            let contenders = (0..self.players.len())
+                .filter(|&i| !self.players[i].folded() && self.players[i].paid >= level);
+            let score = contenders.clone().map(|i| scores[i]).max().unwrap();
+            let splitters = contenders
+                .filter(|&i| scores[i] == score)
+                .collect::<Vec<_>>();
+            let share = amount / splitters.len() as Money;
+            for &splitter in &splitters {
+                self.players[splitter].stack += share;
+            }
+            pots.push(Pot {
+                amount,
+                players: splitters,
+                score,
+            });
+            previous = level;
+        }
+        pots
+    }
+}
+
+/// The blind level a tournament should be at after playing a number of hands.
+pub(crate) fn blind_level(schedule: &BlindSchedule, hands: usize, start: Instant) -> usize {
+    match schedule.level_up {
+        LevelUp::Hands(n) => hands / n.max(1) as usize,
+        LevelUp::Seconds(n) => (start.elapsed().as_secs() / n.max(1)) as usize,
     }
+    .min(schedule.levels.len().saturating_sub(1))
+}
+
+/// The seats of the button and the blinds in a round.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+struct Positions {
+    dealer: usize,
+    small_blind: usize,
+    big_blind: usize,
 }
 
 /// Struct to manage the state of a player
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 struct Player {
     hole_cards: Option<(Card, Card)>,
     stack: Money,
     bet: Money,
+    // everything put in the pot this round, antes included
+    paid: Money,
 }
 
 impl Player {
@@ -266,6 +644,7 @@ impl Player {
             stack,
             bet: ZERO_MONEY,
             hole_cards: None,
+            paid: ZERO_MONEY,
         }
     }
 
@@ -283,10 +662,15 @@ impl Player {
         self.stack + self.bet > ZERO_MONEY
     }
 
+    /// The bet this player makes when pushing all their chips in.
+    fn all_in(&self) -> Money {
+        self.stack + self.bet
+    }
+
     /// Attempt to raise. A player can raise if their stack is sufficiently big.
     /// raise: the amount to which must be raised
     fn raise(&mut self, raise: Money) -> Result<(), ()> {
-        if raise <= self.stack + self.bet {
+        if raise <= self.all_in() {
             self.stack -= raise - self.bet;
             self.bet = raise;
             Ok(())
@@ -295,6 +679,15 @@ impl Player {
         }
     }
 
+    /// Pay an ante straight into the pot, or as much of it as the stack allows.
+    /// Returns: the amount paid
+    fn ante(&mut self, ante: Money) -> Money {
+        let paid = ante.min(self.stack);
+        self.stack -= paid;
+        self.paid += paid;
+        paid
+    }
+
     /// if calling on more than you have, you are all in.
     /// bet: the current bet that must be called.
     fn call(&mut self, bet: Money) {
@@ -302,8 +695,8 @@ impl Player {
             self.stack -= bet - self.bet;
             self.bet = bet;
         } else {
+            self.bet += self.stack;
             self.stack = ZERO_MONEY;
-            self.bet = bet;
         }
     }
 
@@ -315,10 +708,21 @@ impl Player {
     fn yield_bet(&mut self) -> Money {
         let bet = self.bet;
         self.bet = ZERO_MONEY;
+        self.paid += bet;
         bet
     }
 }
 
+/// The message a panic was raised with.
+fn panic_message(panic: &Box<dyn Any + Send>) -> &str {
+    match panic.downcast_ref::<&str>() {
+        Some(message) => message,
+        None => panic
+            .downcast_ref::<String>()
+            .map_or("unknown", String::as_str),
+    }
+}
+
 struct Deck {
     cards: Vec<Card>,
 }
@@ -359,3 +763,843 @@ pot-limit:
 can only double pot
 
 */
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::VecDeque;
+
+    /// Plays back a fixed list of actions and records who was asked to act.
+    struct Script {
+        actions: VecDeque<PlayerAction>,
+        asked: Vec<usize>,
+        // the minimum raise each asked player was told
+        min_raises: Vec<Option<Money>>,
+        straddle: bool,
+        // (player, action, amount, stack) of every PlayerActed message
+        acted: Vec<(usize, Action, Money, Money)>,
+    }
+
+    fn script(actions: Vec<PlayerAction>) -> Script {
+        Script {
+            actions: actions.into_iter().collect(),
+            asked: Vec::new(),
+            min_raises: Vec::new(),
+            straddle: false,
+            acted: Vec::new(),
+        }
+    }
+
+    impl Callback for Script {
+        fn callback(&mut self, message: Message) -> Result<Response, Error> {
+            match message {
+                Message::RequestAction {
+                    player, min_raise, ..
+                } => {
+                    self.asked.push(player);
+                    self.min_raises.push(min_raise);
+                    let action = self.actions.pop_front().expect("script ran out of actions");
+                    Ok(Response::Action(action))
+                }
+                Message::RequestStraddle { .. } => Ok(Response::Straddle(self.straddle)),
+                Message::PlayerActed {
+                    player,
+                    action,
+                    amount,
+                    stack,
+                } => {
+                    self.acted.push((player, action, amount, stack));
+                    Ok(Response::Ack)
+                }
+                _ => Ok(Response::Ack),
+            }
+        }
+    }
+
+    struct Case {
+        name: &'static str,
+        // (stack, bet) of each player at the start of the betting round
+        players: Vec<(Money, Money)>,
+        first_player: usize,
+        actions: Vec<(usize, PlayerAction)>,
+        // stacks after the betting round, or the player that made an illegal move
+        expected: Result<Vec<Money>, usize>,
+    }
+
+    fn card() -> Card {
+        Card {
+            suit: Suit::Hearts,
+            rank: 2,
+        }
+    }
+
+    #[test]
+    fn test_no_limit_betting_round() {
+        use PlayerAction::*;
+        let cases = vec![
+            Case {
+                name: "limp around, big blind checks",
+                players: vec![(100, 0), (99, 1), (98, 2)],
+                first_player: 0,
+                actions: vec![(0, Call), (1, Call), (2, Call)],
+                expected: Ok(vec![98, 98, 98]),
+            },
+            Case {
+                name: "big blind has the option to raise",
+                players: vec![(100, 0), (99, 1), (98, 2)],
+                first_player: 0,
+                actions: vec![(0, Call), (1, Call), (2, Raise(4)), (0, Call), (1, Fold)],
+                expected: Ok(vec![94, 98, 94]),
+            },
+            Case {
+                name: "everybody folds to the big blind",
+                players: vec![(100, 0), (99, 1), (98, 2)],
+                first_player: 0,
+                actions: vec![(0, Fold), (1, Fold)],
+                expected: Ok(vec![100, 99, 98]),
+            },
+            Case {
+                name: "raise smaller than the big blind",
+                players: vec![(100, 0), (99, 1), (98, 2)],
+                first_player: 0,
+                actions: vec![(0, Raise(1))],
+                expected: Err(0),
+            },
+            Case {
+                name: "re-raise smaller than the previous raise",
+                players: vec![(100, 0), (99, 1), (98, 2)],
+                first_player: 0,
+                actions: vec![(0, Raise(6)), (1, Raise(5))],
+                expected: Err(1),
+            },
+            Case {
+                name: "re-raise of exactly the previous raise",
+                players: vec![(100, 0), (99, 1), (98, 2)],
+                first_player: 0,
+                actions: vec![(0, Raise(6)), (1, Raise(6)), (2, Fold), (0, Call)],
+                expected: Ok(vec![86, 86, 98]),
+            },
+            Case {
+                name: "raise more than the stack",
+                players: vec![(10, 0), (10, 0)],
+                first_player: 0,
+                actions: vec![(0, Raise(11))],
+                expected: Err(0),
+            },
+            Case {
+                name: "check around",
+                players: vec![(10, 0), (10, 0), (10, 0)],
+                first_player: 1,
+                actions: vec![(1, Call), (2, Call), (0, Call)],
+                expected: Ok(vec![10, 10, 10]),
+            },
+            Case {
+                name: "calling more than the stack is all-in for less",
+                players: vec![(100, 0), (5, 0)],
+                first_player: 0,
+                actions: vec![(0, Raise(10)), (1, Call)],
+                expected: Ok(vec![90, 0]),
+            },
+            Case {
+                name: "all-in for less than a full raise is allowed",
+                players: vec![(100, 0), (15, 0), (100, 0)],
+                first_player: 0,
+                actions: vec![(0, Raise(10)), (1, Raise(5)), (2, Call), (0, Call)],
+                expected: Ok(vec![85, 0, 85]),
+            },
+            Case {
+                name: "raise for less than a full raise without going all-in",
+                players: vec![(100, 0), (16, 0), (100, 0)],
+                first_player: 0,
+                actions: vec![(0, Raise(10)), (1, Raise(5))],
+                expected: Err(1),
+            },
+            Case {
+                name: "short all-in does not reopen the betting",
+                players: vec![(100, 0), (15, 0), (100, 0)],
+                first_player: 0,
+                actions: vec![(0, Raise(10)), (1, Raise(5)), (2, Call), (0, Raise(10))],
+                expected: Err(0),
+            },
+            Case {
+                name: "short all-in leaves the raise open to players yet to act",
+                players: vec![(100, 0), (15, 0), (100, 0)],
+                first_player: 0,
+                actions: vec![
+                    (0, Raise(10)),
+                    (1, Raise(5)),
+                    (2, Raise(10)),
+                    (0, Raise(10)),
+                    (2, Call),
+                ],
+                expected: Ok(vec![65, 0, 65]),
+            },
+            Case {
+                name: "short all-ins adding up to a full raise reopen the betting",
+                players: vec![(100, 0), (15, 0), (21, 0), (100, 0)],
+                first_player: 0,
+                actions: vec![
+                    (0, Raise(10)),
+                    (1, Raise(5)),
+                    (2, Raise(6)),
+                    (3, Call),
+                    (0, Raise(10)),
+                    (3, Call),
+                ],
+                expected: Ok(vec![69, 0, 0, 69]),
+            },
+            Case {
+                name: "minimum raise stays the last full raise after a short all-in",
+                players: vec![(100, 0), (15, 0), (100, 0)],
+                first_player: 0,
+                actions: vec![(0, Raise(10)), (1, Raise(5)), (2, Raise(9))],
+                expected: Err(2),
+            },
+            Case {
+                name: "player facing an all-in must still act",
+                players: vec![(50, 0), (100, 0)],
+                first_player: 0,
+                actions: vec![(0, Raise(50)), (1, Call)],
+                expected: Ok(vec![0, 50]),
+            },
+            Case {
+                name: "no raising when nobody is left to respond",
+                players: vec![(50, 0), (100, 0)],
+                first_player: 0,
+                actions: vec![(0, Raise(50)), (1, Raise(50))],
+                expected: Err(1),
+            },
+            Case {
+                name: "raise too big to add to the bet",
+                players: vec![(100, 0), (99, 1), (98, 2)],
+                first_player: 0,
+                actions: vec![(0, Raise(Money::MAX))],
+                expected: Err(0),
+            },
+            Case {
+                name: "small blind all-in on the blind",
+                players: vec![(100, 0), (0, 1), (98, 2)],
+                first_player: 0,
+                actions: vec![(0, Raise(4)), (2, Call)],
+                expected: Ok(vec![94, 0, 94]),
+            },
+            Case {
+                name: "no action when all but one player are all-in",
+                players: vec![(0, 0), (100, 0), (0, 0)],
+                first_player: 0,
+                actions: vec![],
+                expected: Ok(vec![0, 100, 0]),
+            },
+        ];
+
+        for case in cases {
+            let total = case.players.iter().map(|(s, b)| s + b).sum::<Money>();
+            let mut table = Table::new(
+                GameType::NoLimit,
+                1,
+                2,
+                Ante::None,
+                Straddle::None,
+                case.players.iter().map(|(s, _)| *s).collect(),
+                script(case.actions.iter().map(|(_, a)| *a).collect()),
+            );
+            for (player, (_, bet)) in table.players.iter_mut().zip(&case.players) {
+                player.bet = *bet;
+                player.hole_cards = Some((card(), card()));
+            }
+
+            let result = table.betting_round(case.first_player, 2, ZERO_MONEY);
+            let expected_order = case.actions.iter().map(|(p, _)| *p).collect::<Vec<_>>();
+            assert_eq!(table.callback.asked, expected_order, "{}", case.name);
+            match (result, case.expected) {
+                (Ok(pot), Ok(stacks)) => {
+                    let actual = table.players.iter().map(|p| p.stack).collect::<Vec<_>>();
+                    assert_eq!(actual, stacks, "{}", case.name);
+                    assert_eq!(pot, total - stacks.iter().sum::<Money>(), "{}", case.name);
+                    // what every player paid, which the pots are made of
+                    for ((player, (stack, bet)), left) in
+                        table.players.iter().zip(&case.players).zip(&stacks)
+                    {
+                        assert_eq!(player.paid, stack + bet - left, "{}", case.name);
+                    }
+                }
+                (Err(e), Err(player)) => {
+                    assert_eq!(e.player, player, "{}", case.name);
+                    assert_eq!(e.error, ErrorMessage::BetNotAllowed, "{}", case.name);
+                }
+                (result, _) => panic!("{}: unexpected result {:?}", case.name, result),
+            }
+        }
+    }
+
+    #[test]
+    fn test_min_raise() {
+        use PlayerAction::*;
+        // (stacks, actions, the minimum raise every player was told)
+        let cases = vec![
+            (
+                vec![100, 100, 100],
+                vec![Raise(10), Raise(20), Fold, Call],
+                vec![Some(2), Some(10), Some(20), Some(20)],
+            ),
+            // the short all-in does not reopen the betting for the raiser
+            (
+                vec![100, 15, 100],
+                vec![Raise(10), Raise(5), Call, Call],
+                vec![Some(2), Some(10), Some(10), None],
+            ),
+            // nobody is left to respond to a raise
+            (vec![50, 100], vec![Raise(50), Call], vec![Some(2), None]),
+        ];
+        for (stacks, actions, expected) in cases {
+            let mut table = Table::new(
+                GameType::NoLimit,
+                1,
+                2,
+                Ante::None,
+                Straddle::None,
+                stacks,
+                script(actions),
+            );
+            for player in &mut table.players {
+                player.hole_cards = Some((card(), card()));
+            }
+            table.betting_round(0, 2, ZERO_MONEY).unwrap();
+            assert_eq!(table.callback.min_raises, expected);
+        }
+    }
+
+    #[test]
+    fn test_showdown() {
+        let card = |rank, suit| Card { rank, suit };
+        let board = [
+            card(2, Suit::Clubs),
+            card(7, Suit::Diamonds),
+            card(9, Suit::Clubs),
+            card(11, Suit::Hearts),
+            card(3, Suit::Spades),
+        ];
+        let pair = |rank| Some((card(rank, Suit::Spades), card(rank, Suit::Hearts)));
+        // (hole cards and paid of every player, expected stacks, expected
+        // amount and players of every pot)
+        let cases = vec![
+            // a single pot for everyone that paid the same
+            (
+                vec![(pair(14), 10), (pair(13), 10), (None, 4)],
+                vec![24, 0, 0],
+                vec![(24, vec![0])],
+            ),
+            // the short all-in only wins what they matched from everyone
+            (
+                vec![(pair(14), 10), (pair(13), 50), (pair(12), 50), (None, 20)],
+                vec![40, 90, 0, 0],
+                vec![(40, vec![0]), (90, vec![1])],
+            ),
+            // all-in on an ante
+            (
+                vec![(pair(14), 1), (pair(13), 30), (pair(12), 30)],
+                vec![3, 58, 0],
+                vec![(3, vec![0]), (58, vec![1])],
+            ),
+            // uncalled chips go back to whoever bet them
+            (
+                vec![(pair(12), 60), (pair(13), 40)],
+                vec![20, 80],
+                vec![(80, vec![1]), (20, vec![0])],
+            ),
+            // split side pot, the bank keeps the odd chip
+            (
+                vec![
+                    (pair(14), 5),
+                    (pair(4), 10),
+                    (pair(4), 10),
+                    (pair(5), 3),
+                    (None, 6),
+                ],
+                vec![23, 5, 5, 0, 0],
+                vec![(15, vec![0]), (8, vec![0]), (11, vec![1, 2])],
+            ),
+        ];
+        for (players, stacks, pots) in cases {
+            let mut table = Table::new(
+                GameType::NoLimit,
+                1,
+                2,
+                Ante::None,
+                Straddle::None,
+                vec![0; players.len()],
+                script(vec![]),
+            );
+            for (player, &(hole_cards, paid)) in table.players.iter_mut().zip(&players) {
+                player.hole_cards = hole_cards;
+                player.paid = paid;
+            }
+            let actual = table.showdown(&board);
+            let amounts = actual
+                .iter()
+                .map(|pot| (pot.amount, pot.players.clone()))
+                .collect::<Vec<_>>();
+            assert_eq!(amounts, pots, "{:?}", players);
+            let actual = table.players.iter().map(|p| p.stack).collect::<Vec<_>>();
+            assert_eq!(actual, stacks, "{:?}", players);
+        }
+    }
+
+    #[test]
+    fn test_positions() {
+        let positions = |dealer, small_blind, big_blind| Positions {
+            dealer,
+            small_blind,
+            big_blind,
+        };
+        // (stacks, previous positions, expected positions)
+        let cases = vec![
+            (vec![10, 10, 10, 10], None, positions(0, 1, 2)),
+            (vec![0, 10, 10, 10], None, positions(1, 2, 3)),
+            (vec![10, 10], None, positions(0, 0, 1)),
+            (
+                vec![10, 10, 10, 10],
+                Some(positions(0, 1, 2)),
+                positions(1, 2, 3),
+            ),
+            (
+                vec![10, 10, 10, 10],
+                Some(positions(2, 3, 0)),
+                positions(3, 0, 1),
+            ),
+            // heads-up the button alternates and posts the small blind
+            (vec![10, 10], Some(positions(0, 0, 1)), positions(1, 1, 0)),
+            (
+                vec![10, 0, 10, 0],
+                Some(positions(0, 0, 2)),
+                positions(2, 2, 0),
+            ),
+            // the previous big blind busted, so the small blind is dead
+            (
+                vec![10, 10, 0, 10],
+                Some(positions(0, 1, 2)),
+                positions(1, 2, 3),
+            ),
+            // the previous small blind busted, so the button is dead
+            (
+                vec![10, 0, 10, 10],
+                Some(positions(0, 1, 2)),
+                positions(1, 2, 3),
+            ),
+            // the big blind skips busted players
+            (
+                vec![10, 10, 10, 0, 10],
+                Some(positions(0, 1, 2)),
+                positions(1, 2, 4),
+            ),
+            // going heads-up, the previous big blind does not post it again
+            (
+                vec![0, 10, 10],
+                Some(positions(0, 1, 2)),
+                positions(2, 2, 1),
+            ),
+        ];
+
+        for (stacks, previous, expected) in cases {
+            let mut table = Table::new(
+                GameType::NoLimit,
+                1,
+                2,
+                Ante::None,
+                Straddle::None,
+                stacks.clone(),
+                script(vec![]),
+            );
+            table.positions = previous;
+            assert_eq!(
+                table.next_positions(),
+                expected,
+                "stacks {:?}, previous {:?}",
+                stacks,
+                previous
+            );
+        }
+    }
+
+    #[test]
+    fn test_heads_up_action_order() {
+        let mut table = Table::new(
+            GameType::NoLimit,
+            1,
+            2,
+            Ante::None,
+            Straddle::None,
+            vec![100, 100],
+            script(vec![PlayerAction::Call; 16]),
+        );
+        table.play_round().unwrap();
+        assert_eq!(table.players[0].stack + table.players[1].stack, 200);
+        // the button is first pre-flop and last after the flop
+        assert_eq!(table.callback.asked, vec![0, 1, 1, 0, 1, 0, 1, 0]);
+
+        table.callback.asked.clear();
+        table.play_round().unwrap();
+        assert_eq!(table.callback.asked, vec![1, 0, 0, 1, 0, 1, 0, 1]);
+    }
+
+    #[test]
+    fn test_dead_small_blind() {
+        let mut table = Table::new(
+            GameType::NoLimit,
+            1,
+            2,
+            Ante::None,
+            Straddle::None,
+            vec![100, 100, 0, 100],
+            script(vec![PlayerAction::Fold; 2]),
+        );
+        table.positions = Some(Positions {
+            dealer: 0,
+            small_blind: 1,
+            big_blind: 2,
+        });
+        table.play_round().unwrap();
+        // nobody posts the small blind, the big blind wins only their own bet
+        assert_eq!(table.callback.asked, vec![0, 1]);
+        let stacks = table.players.iter().map(|p| p.stack).collect::<Vec<_>>();
+        assert_eq!(stacks, vec![100, 100, 0, 100]);
+    }
+
+    #[test]
+    fn test_forced_bets() {
+        use PlayerAction::*;
+        // (ante, straddle, whether to straddle, stacks, actions, who is asked,
+        //  stacks after the round or the player that made an illegal move)
+        let cases = vec![
+            (
+                Ante::PerPlayer(1),
+                Straddle::None,
+                false,
+                vec![100, 100, 100],
+                vec![Fold, Fold],
+                vec![0, 1],
+                Ok(vec![99, 98, 103]),
+            ),
+            (
+                Ante::BigBlind(3),
+                Straddle::None,
+                false,
+                vec![100, 100, 100],
+                vec![Fold, Fold],
+                vec![0, 1],
+                Ok(vec![100, 99, 101]),
+            ),
+            (
+                Ante::BigBlind(3),
+                Straddle::None,
+                false,
+                vec![100, 100, 3],
+                vec![Fold, Fold],
+                vec![0, 1],
+                Ok(vec![100, 99, 4]),
+            ),
+            (
+                Ante::None,
+                Straddle::Mandatory,
+                false,
+                vec![100, 100, 100, 100],
+                vec![Fold, Fold, Fold],
+                vec![0, 1, 2],
+                Ok(vec![100, 99, 98, 103]),
+            ),
+            (
+                Ante::None,
+                Straddle::Mandatory,
+                false,
+                vec![100, 100, 100, 100],
+                vec![Raise(2)],
+                vec![0],
+                Err(0),
+            ),
+            (
+                Ante::None,
+                Straddle::Voluntary,
+                true,
+                vec![100, 100, 100, 100],
+                vec![Call, Fold, Fold, Fold],
+                vec![0, 1, 2, 3],
+                Ok(vec![107, 99, 98, 96]),
+            ),
+            (
+                Ante::None,
+                Straddle::Voluntary,
+                false,
+                vec![100, 100, 100, 100],
+                vec![Fold, Fold, Fold],
+                vec![3, 0, 1],
+                Ok(vec![100, 99, 101, 100]),
+            ),
+            // no straddles heads-up
+            (
+                Ante::None,
+                Straddle::Mandatory,
+                false,
+                vec![100, 100],
+                vec![Fold],
+                vec![0],
+                Ok(vec![99, 101]),
+            ),
+        ];
+
+        for (ante, straddle, straddles, stacks, actions, asked, expected) in cases {
+            let mut table = Table::new(
+                GameType::NoLimit,
+                1,
+                2,
+                ante,
+                straddle,
+                stacks.clone(),
+                Script {
+                    straddle: straddles,
+                    ..script(actions)
+                },
+            );
+            let result = table.play_round();
+            assert_eq!(table.callback.asked, asked, "{:?} {:?}", ante, straddle);
+            match (result, expected) {
+                (Ok(()), Ok(expected)) => {
+                    let actual = table.players.iter().map(|p| p.stack).collect::<Vec<_>>();
+                    assert_eq!(actual, expected, "{:?} {:?}", ante, straddle);
+                }
+                (Err(e), Err(player)) => assert_eq!(e.player, player),
+                (result, _) => panic!("{:?} {:?}: unexpected {:?}", ante, straddle, result),
+            }
+        }
+    }
+
+    #[test]
+    fn test_player_acted() {
+        use PlayerAction::*;
+        // the short stack goes all-in, which leaves nothing to decide after
+        // the flop
+        let mut table = Table::new(
+            GameType::NoLimit,
+            1,
+            2,
+            Ante::PerPlayer(1),
+            Straddle::None,
+            vec![100, 100, 5],
+            script(vec![Call, Call, Raise(2), Fold, Call]),
+        );
+        table.play_round().unwrap();
+        assert_eq!(
+            table.callback.acted,
+            vec![
+                (0, Action::Ante, 1, 99),
+                (1, Action::Ante, 1, 99),
+                (2, Action::Ante, 1, 4),
+                (1, Action::SmallBlind, 1, 98),
+                (2, Action::BigBlind, 2, 2),
+                (0, Action::Call, 2, 97),
+                (1, Action::Call, 1, 97),
+                (2, Action::Raise, 2, 0),
+                (0, Action::Fold, 0, 97),
+                (1, Action::Call, 2, 95),
+            ]
+        );
+    }
+
+    /// Calls every bet and keeps all messages.
+    struct Log(Vec<Message>);
+
+    impl Callback for &mut Log {
+        fn callback(&mut self, message: Message) -> Result<Response, Error> {
+            let response = match message {
+                Message::RequestAction { .. } => Response::Action(PlayerAction::Call),
+                _ => Response::Ack,
+            };
+            self.0.push(message);
+            Ok(response)
+        }
+    }
+
+    #[test]
+    fn test_tournament() {
+        let blinds = |small_blind, big_blind| BlindLevel {
+            small_blind,
+            big_blind,
+            ante: Ante::None,
+        };
+        let schedule = BlindSchedule {
+            levels: vec![
+                blinds(1, 2),
+                blinds(5, 10),
+                blinds(25, 50),
+                blinds(100, 200),
+            ],
+            level_up: LevelUp::Hands(2),
+        };
+        let mut log = Log(Vec::new());
+        Table::new(
+            GameType::NoLimit,
+            1,
+            2,
+            Ante::None,
+            Straddle::None,
+            vec![100, 100, 0, 100, 100],
+            &mut log,
+        )
+        .play_tournament(&schedule);
+
+        let levels = log
+            .0
+            .iter()
+            .filter_map(|m| match m {
+                Message::BlindsUp { level, .. } => Some(*level),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        // the tournament can be over before the last level
+        assert_eq!(&levels[..2], &[0, 1]);
+        assert!(levels.windows(2).all(|w| w[1] == w[0] + 1));
+
+        let eliminated = log
+            .0
+            .iter()
+            .filter_map(|m| match m {
+                Message::Eliminated { player, place } => Some((*player, *place)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            eliminated
+                .iter()
+                .map(|(_, place)| *place)
+                .collect::<Vec<_>>(),
+            vec![4, 3, 2]
+        );
+
+        match log.0.last() {
+            Some(Message::Standings(standings)) => {
+                let mut expected = eliminated.iter().map(|(p, _)| *p).collect::<Vec<_>>();
+                expected.reverse();
+                assert_eq!(&standings[1..], &expected[..]);
+                let mut players = standings.clone();
+                players.sort();
+                assert_eq!(players, vec![0, 1, 3, 4]);
+            }
+            m => panic!("expected the standings, got {:?}", m),
+        }
+    }
+
+    /// Calls every bet, and asks the table to stop after a number of rounds.
+    struct Stopping {
+        rounds: usize,
+        messages: Vec<Message>,
+    }
+
+    impl Callback for &mut Stopping {
+        fn callback(&mut self, message: Message) -> Result<Response, Error> {
+            let response = match message {
+                Message::RequestAction { .. } => Response::Action(PlayerAction::Call),
+                _ => Response::Ack,
+            };
+            self.messages.push(message);
+            Ok(response)
+        }
+
+        fn stopping(&mut self) -> bool {
+            if self.rounds == 0 {
+                return true;
+            }
+            self.rounds -= 1;
+            false
+        }
+    }
+
+    #[test]
+    fn test_stopping() {
+        for rounds in 0..3 {
+            let mut stopping = Stopping {
+                rounds,
+                messages: Vec::new(),
+            };
+            Table::new(
+                GameType::NoLimit,
+                1,
+                2,
+                Ante::None,
+                Straddle::None,
+                vec![100, 100, 0, 100],
+                &mut stopping,
+            )
+            .play();
+
+            let played = stopping
+                .messages
+                .iter()
+                .filter(|m| matches!(m, Message::Showdown { .. }))
+                .count();
+            assert_eq!(played, rounds);
+            match stopping.messages.last() {
+                Some(Message::Shutdown { stacks }) => {
+                    assert_eq!(stacks.len(), 4);
+                    assert_eq!(stacks[2], 0);
+                    assert_eq!(stacks.iter().sum::<Money>(), 300);
+                }
+                m => panic!("expected the shutdown notice, got {:?}", m),
+            }
+        }
+    }
+
+    /// Calls every bet, and panics when dealing the flop of the given round.
+    struct Panicking {
+        round: usize,
+        messages: Vec<Message>,
+    }
+
+    impl Callback for &mut Panicking {
+        fn callback(&mut self, message: Message) -> Result<Response, Error> {
+            let response = match message {
+                Message::RequestAction { .. } => Response::Action(PlayerAction::Call),
+                Message::Flop(..) if self.round == 0 => panic!("lost the connection"),
+                Message::Flop(..) => {
+                    self.round -= 1;
+                    Response::Ack
+                }
+                _ => Response::Ack,
+            };
+            self.messages.push(message);
+            Ok(response)
+        }
+    }
+
+    #[test]
+    fn test_panicking() {
+        let mut panicking = Panicking {
+            round: 1,
+            messages: Vec::new(),
+        };
+        let finished = Table::new(
+            GameType::NoLimit,
+            1,
+            2,
+            Ante::None,
+            Straddle::None,
+            vec![100, 100, 100],
+            &mut panicking,
+        )
+        .play();
+        assert!(!finished);
+
+        // the second round is undone
+        let after_first = panicking
+            .messages
+            .iter()
+            .rev()
+            .find_map(|m| match m {
+                Message::Showdown { stacks, .. } => Some(stacks.clone()),
+                _ => None,
+            })
+            .unwrap();
+        match panicking.messages.last() {
+            Some(Message::Aborted { stacks }) => assert_eq!(stacks, &after_first),
+            m => panic!("expected the table to abort, got {:?}", m),
+        }
+    }
+}
@@ -0,0 +1,190 @@
+use num_traits::FromPrimitive;
+use rand::seq::index::sample;
+use rand::thread_rng;
+
+use crate::communication::{Card, Suit};
+use crate::score::Score;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum EquityError {
+    // fewer than two hands to compare
+    TooFewHands,
+    // more than five cards on the board
+    TooManyCards,
+    // the same card was dealt twice
+    DuplicateCard(Card),
+    // not enough cards are left to finish the board
+    TooManyHands,
+}
+
+/// The share of the pot every hand wins on average, when the rest of the
+/// board is dealt. Ties split the pot. Every way to finish the board is
+/// counted when there are at most `samples` of them, otherwise `samples`
+/// random boards are dealt.
+/// hands: the hole cards of the players that are still in the hand
+/// board: the cards on the table, none before the flop
+pub fn equity(
+    hands: &[(Card, Card)],
+    board: &[Card],
+    samples: usize,
+) -> Result<Vec<f64>, EquityError> {
+    if hands.len() < 2 {
+        return Err(EquityError::TooFewHands);
+    }
+    if board.len() > 5 {
+        return Err(EquityError::TooManyCards);
+    }
+    let mut dealt = board.to_vec();
+    dealt.extend(hands.iter().flat_map(|&(c1, c2)| vec![c1, c2]));
+    for (i, card) in dealt.iter().enumerate() {
+        if dealt[..i].contains(card) {
+            return Err(EquityError::DuplicateCard(*card));
+        }
+    }
+    let deck = (0..52)
+        .map(|i| Card {
+            suit: Suit::from_u8(i / 13).unwrap(),
+            rank: 2 + i % 13,
+        })
+        .filter(|card| !dealt.contains(card))
+        .collect::<Vec<_>>();
+
+    let missing = 5 - board.len();
+    if deck.len() < missing {
+        return Err(EquityError::TooManyHands);
+    }
+    let mut shares = vec![0.0; hands.len()];
+    let mut boards = 0;
+    let mut deal = |rest: &[usize]| {
+        let mut cards = board.to_vec();
+        cards.extend(rest.iter().map(|&i| deck[i]));
+        let scores = hands
+            .iter()
+            .map(|&(c1, c2)| {
+                let mut cards = cards.clone();
+                cards.extend(&[c1, c2]);
+                Score::calculate(cards)
+            })
+            .collect::<Vec<_>>();
+        let best = scores.iter().max().unwrap();
+        let winners = scores.iter().filter(|&score| score == best).count();
+        for (share, score) in shares.iter_mut().zip(&scores) {
+            if score == best {
+                *share += 1.0 / winners as f64;
+            }
+        }
+        boards += 1;
+    };
+
+    if combinations(deck.len(), missing) <= samples {
+        let mut rest = (0..missing).collect::<Vec<_>>();
+        loop {
+            deal(&rest);
+            // move on to the next combination of cards, in lexicographic order
+            let mut i = missing;
+            while i > 0 && rest[i - 1] == deck.len() - missing + i - 1 {
+                i -= 1;
+            }
+            if i == 0 {
+                break;
+            }
+            rest[i - 1] += 1;
+            for j in i..missing {
+                rest[j] = rest[j - 1] + 1;
+            }
+        }
+    } else {
+        let mut rng = thread_rng();
+        for _ in 0..samples.max(1) {
+            deal(&sample(&mut rng, deck.len(), missing).into_vec());
+        }
+    }
+    Ok(shares
+        .into_iter()
+        .map(|share| share / boards as f64)
+        .collect())
+}
+
+/// The number of ways to pick k out of n things.
+fn combinations(n: usize, k: usize) -> usize {
+    (0..k).fold(1, |count, i| count * (n - i) / (i + 1))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn card(rank: u8, suit: Suit) -> Card {
+        Card { rank, suit }
+    }
+
+    #[test]
+    fn test_equity() {
+        let aces = (card(14, Suit::Spades), card(14, Suit::Hearts));
+        let kings = (card(13, Suit::Spades), card(13, Suit::Hearts));
+        let flop = [
+            card(2, Suit::Clubs),
+            card(7, Suit::Diamonds),
+            card(9, Suit::Clubs),
+        ];
+        let river = [
+            card(2, Suit::Clubs),
+            card(7, Suit::Diamonds),
+            card(9, Suit::Clubs),
+            card(13, Suit::Clubs),
+            card(3, Suit::Hearts),
+        ];
+        let big_slick = (card(14, Suit::Clubs), card(13, Suit::Diamonds));
+        let other_slick = (card(14, Suit::Diamonds), card(13, Suit::Spades));
+        let dry = [
+            card(2, Suit::Clubs),
+            card(7, Suit::Diamonds),
+            card(9, Suit::Clubs),
+            card(11, Suit::Hearts),
+            card(3, Suit::Spades),
+        ];
+        // (hands, board, expected)
+        let cases = vec![
+            (vec![aces, kings], &river[..], vec![0.0, 1.0]),
+            (vec![big_slick, other_slick], &dry, vec![0.5, 0.5]),
+            // of the 990 turns and rivers, the kings win the 2 * 41 with one
+            // king and no ace, and the one with both kings
+            (vec![aces, kings], &flop, vec![907.0 / 990.0, 83.0 / 990.0]),
+        ];
+        for (hands, board, expected) in cases {
+            let shares = equity(&hands, board, 1000).unwrap();
+            for (share, expected) in shares.iter().zip(&expected) {
+                assert!((share - expected).abs() < 1e-9, "{:?}", shares);
+            }
+        }
+
+        // before the flop there are too many boards to count them all
+        let shares = equity(&[aces, kings], &[], 2000).unwrap();
+        assert!(shares[0] > 0.7 && shares[0] < 0.9, "{:?}", shares);
+        assert!((shares[0] + shares[1] - 1.0).abs() < 1e-9);
+
+        assert_eq!(equity(&[aces], &[], 10), Err(EquityError::TooFewHands));
+        assert_eq!(
+            equity(&[aces, kings], &[card(2, Suit::Clubs); 6], 10),
+            Err(EquityError::TooManyCards)
+        );
+        assert_eq!(
+            equity(&[aces, aces], &[], 10),
+            Err(EquityError::DuplicateCard(aces.0))
+        );
+
+        // 24 hands leave 4 cards for the board, 23 hands leave 6
+        let deck = (0..52)
+            .map(|i| card(2 + i % 13, Suit::from_u8(i / 13).unwrap()))
+            .collect::<Vec<_>>();
+        let hands = deck
+            .chunks(2)
+            .map(|pair| (pair[0], pair[1]))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            equity(&hands[..24], &[], 10),
+            Err(EquityError::TooManyHands)
+        );
+        assert!(equity(&hands[..23], &[], 10).is_ok());
+    }
+}
@@ -0,0 +1,418 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+
+use crate::communication::{Leaderboard, Money, Ranking};
+
+// the rating of a player that has not finished a game yet
+const INITIAL_RATING: f64 = 1500.0;
+// how much the ratings of two players change when one finishes above the other
+const K_FACTOR: f64 = 32.0;
+
+/// A single line of the ledger file.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub enum Record {
+    // a player brought chips to a cash table
+    BuyIn {
+        name: String,
+        table: usize,
+        chips: Money,
+    },
+    // a player left a cash table with their chips
+    CashOut {
+        name: String,
+        table: usize,
+        chips: Money,
+    },
+    // the chips every player at the table won or lost in a hand
+    Hand {
+        table: usize,
+        big_blind: Money,
+        results: Vec<(String, i64)>,
+    },
+    // the players of a finished game or tournament, the winner first
+    Game {
+        table: usize,
+        standings: Vec<String>,
+    },
+}
+
+/// What the ledger knows about a player.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct Account {
+    // the chips cashed out minus the chips bought in
+    pub bankroll: i64,
+    pub bought_in: u64,
+    pub cashed_out: u64,
+    pub hands: u64,
+    // the chips won or lost in all hands played, at any kind of table
+    pub net: i64,
+    pub games: u64,
+    pub rating: f64,
+    // the sum and the sum of squares of the big blinds won in each hand
+    big_blinds: f64,
+    big_blinds_squared: f64,
+}
+
+impl Default for Account {
+    fn default() -> Account {
+        Account {
+            bankroll: 0,
+            bought_in: 0,
+            cashed_out: 0,
+            hands: 0,
+            net: 0,
+            games: 0,
+            rating: INITIAL_RATING,
+            big_blinds: 0.0,
+            big_blinds_squared: 0.0,
+        }
+    }
+}
+
+impl Account {
+    /// Big blinds won per 100 hands.
+    pub fn winrate(&self) -> f64 {
+        if self.hands == 0 {
+            0.0
+        } else {
+            100.0 * self.big_blinds / self.hands as f64
+        }
+    }
+
+    /// The half width of the 95% confidence interval of the winrate, None
+    /// with fewer than two hands.
+    pub fn interval(&self) -> Option<f64> {
+        if self.hands < 2 {
+            return None;
+        }
+        let n = self.hands as f64;
+        let mean = self.big_blinds / n;
+        let variance = ((self.big_blinds_squared - n * mean * mean) / (n - 1.0)).max(0.0);
+        Some(100.0 * 1.96 * (variance / n).sqrt())
+    }
+}
+
+/// The bankrolls and results of all players that ever played, kept in an
+/// append-only file so they survive restarts of the server.
+#[derive(Default)]
+pub struct Ledger {
+    // None keeps the ledger in memory only
+    file: Option<File>,
+    accounts: BTreeMap<String, Account>,
+}
+
+impl Ledger {
+    /// A ledger that is lost when the server stops.
+    pub fn in_memory() -> Ledger {
+        Ledger::default()
+    }
+
+    /// Read the ledger from a file, creating it if it does not exist, and
+    /// append new records to it. A last line that was only partly written
+    /// when the server stopped is cut off, a broken line before it is an
+    /// error.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Ledger> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+        let mut ledger = Ledger::default();
+        let mut reader = BufReader::new(&mut file);
+        let mut line = Vec::new();
+        // the length of the lines read so far
+        let mut length = 0;
+        let mut torn = false;
+        // whether the last line read ends with a line break
+        let mut finished = true;
+        loop {
+            line.clear();
+            let read = reader.read_until(b'\n', &mut line)?;
+            if read == 0 {
+                break;
+            }
+            finished = line.ends_with(b"\n");
+            let text = line.strip_suffix(b"\n").unwrap_or(&line);
+            if !text.is_empty() {
+                match serde_json::from_slice(text) {
+                    Ok(record) => ledger.apply(&record),
+                    Err(_) if reader.fill_buf()?.is_empty() => {
+                        log!("cutting off the unfinished last line of the ledger");
+                        torn = true;
+                        break;
+                    }
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            length += read as u64;
+        }
+        if torn {
+            file.set_len(length)?;
+        } else if !finished {
+            // the last record is whole but its line is not
+            writeln!(file)?;
+        }
+        ledger.file = Some(file);
+        Ok(ledger)
+    }
+
+    /// Add a record, and write it to the file if there is one.
+    pub fn record(&mut self, record: Record) {
+        self.apply(&record);
+        if let Some(file) = &mut self.file {
+            let line = serde_json::to_string(&record).unwrap();
+            if let Err(e) = writeln!(file, "{}", line).and_then(|_| file.flush()) {
+                log!("could not write to the ledger: {}", e);
+            }
+        }
+    }
+
+    /// Make sure everything recorded so far is on disk.
+    pub fn sync(&self) -> io::Result<()> {
+        match &self.file {
+            Some(file) => file.sync_all(),
+            None => Ok(()),
+        }
+    }
+
+    pub fn account(&self, name: &str) -> Option<&Account> {
+        self.accounts.get(name)
+    }
+
+    pub fn accounts(&self) -> &BTreeMap<String, Account> {
+        &self.accounts
+    }
+
+    /// All players ranked by their rating.
+    pub fn leaderboard(&self) -> Leaderboard {
+        let mut players = self
+            .accounts
+            .iter()
+            .map(|(name, account)| Ranking {
+                name: name.clone(),
+                rating: account.rating,
+                games: account.games,
+                hands: account.hands,
+                winrate: account.winrate(),
+                interval: account.interval(),
+                bankroll: account.bankroll,
+            })
+            .collect::<Vec<_>>();
+        players.sort_by(|a, b| b.rating.total_cmp(&a.rating));
+        Leaderboard { players }
+    }
+
+    fn apply(&mut self, record: &Record) {
+        match record {
+            Record::BuyIn { name, chips, .. } => {
+                let account = self.accounts.entry(name.clone()).or_default();
+                account.bankroll -= *chips as i64;
+                account.bought_in += *chips as u64;
+            }
+            Record::CashOut { name, chips, .. } => {
+                let account = self.accounts.entry(name.clone()).or_default();
+                account.bankroll += *chips as i64;
+                account.cashed_out += *chips as u64;
+            }
+            Record::Hand {
+                big_blind, results, ..
+            } => {
+                for (name, net) in results {
+                    let account = self.accounts.entry(name.clone()).or_default();
+                    let big_blinds = *net as f64 / (*big_blind).max(1) as f64;
+                    account.hands += 1;
+                    account.net += net;
+                    account.big_blinds += big_blinds;
+                    account.big_blinds_squared += big_blinds * big_blinds;
+                }
+            }
+            Record::Game { standings, .. } => {
+                let ratings = standings
+                    .iter()
+                    .map(|name| self.accounts.entry(name.clone()).or_default().rating)
+                    .collect::<Vec<_>>();
+                for (name, change) in standings.iter().zip(elo(&ratings)) {
+                    let account = self.accounts.get_mut(name).unwrap();
+                    account.games += 1;
+                    account.rating += change;
+                }
+            }
+        }
+    }
+}
+
+/// The rating changes after a game, treating every player as having beaten
+/// everyone that finished below them.
+/// ratings: the ratings of the players, the winner first
+fn elo(ratings: &[f64]) -> Vec<f64> {
+    let n = ratings.len();
+    let mut changes = vec![0.0; n];
+    if n < 2 {
+        return changes;
+    }
+    let k = K_FACTOR / (n - 1) as f64;
+    for winner in 0..n {
+        for loser in winner + 1..n {
+            let expected = 1.0 / (1.0 + 10f64.powf((ratings[loser] - ratings[winner]) / 400.0));
+            changes[winner] += k * (1.0 - expected);
+            changes[loser] -= k * (1.0 - expected);
+        }
+    }
+    changes
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_ledger() {
+        let path = std::env::temp_dir().join(format!("poker-ledger-{}.jsonl", std::process::id()));
+        fs::remove_file(&path).ok();
+        let records = vec![
+            Record::BuyIn {
+                name: String::from("alice"),
+                table: 0,
+                chips: 100,
+            },
+            Record::BuyIn {
+                name: String::from("bob"),
+                table: 0,
+                chips: 50,
+            },
+            Record::Hand {
+                table: 0,
+                big_blind: 2,
+                results: vec![(String::from("alice"), 20), (String::from("bob"), -20)],
+            },
+            Record::CashOut {
+                name: String::from("alice"),
+                table: 0,
+                chips: 120,
+            },
+        ];
+
+        let mut ledger = Ledger::open(&path).unwrap();
+        for record in records {
+            ledger.record(record);
+        }
+        let alice = Account {
+            bankroll: 20,
+            bought_in: 100,
+            cashed_out: 120,
+            hands: 1,
+            net: 20,
+            big_blinds: 10.0,
+            big_blinds_squared: 100.0,
+            ..Account::default()
+        };
+        assert_eq!(ledger.account("alice"), Some(&alice));
+        drop(ledger);
+
+        // the ledger is read back after a restart and keeps growing
+        let mut ledger = Ledger::open(&path).unwrap();
+        assert_eq!(ledger.account("alice"), Some(&alice));
+        ledger.record(Record::CashOut {
+            name: String::from("bob"),
+            table: 0,
+            chips: 30,
+        });
+        drop(ledger);
+        let ledger = Ledger::open(&path).unwrap();
+        let bob = ledger.account("bob").unwrap();
+        assert_eq!((bob.bankroll, bob.hands, bob.net), (-20, 1, -20));
+        assert_eq!(ledger.accounts().len(), 2);
+        drop(ledger);
+
+        // a record that was cut short by a crash is dropped, and the next
+        // record goes on a line of its own
+        let mut text = fs::read_to_string(&path).unwrap();
+        let whole = text.len();
+        text.push_str("{\"CashOut\":{\"name\":\"bo");
+        fs::write(&path, &text).unwrap();
+        let mut ledger = Ledger::open(&path).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), whole as u64);
+        assert_eq!(ledger.account("bob").unwrap().bankroll, -20);
+        ledger.record(Record::BuyIn {
+            name: String::from("bob"),
+            table: 1,
+            chips: 10,
+        });
+        drop(ledger);
+        let ledger = Ledger::open(&path).unwrap();
+        assert_eq!(ledger.account("bob").unwrap().bankroll, -30);
+
+        // a last record without its line break is kept
+        let text = fs::read_to_string(&path).unwrap();
+        fs::write(&path, text.trim_end()).unwrap();
+        let ledger = Ledger::open(&path).unwrap();
+        assert_eq!(ledger.account("bob").unwrap().bankroll, -30);
+        assert!(fs::read_to_string(&path).unwrap().ends_with('\n'));
+        drop(ledger);
+
+        // a broken line in the middle of the ledger is not skipped
+        let mut lines = fs::read_to_string(&path).unwrap();
+        lines.insert_str(0, "{\"BuyIn\":\n");
+        fs::write(&path, lines).unwrap();
+        assert!(Ledger::open(&path).is_err());
+        fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn test_elo() {
+        // (ratings, winner first, rating changes)
+        let cases = vec![
+            (vec![1500.0, 1500.0], vec![16.0, -16.0]),
+            (vec![1500.0, 1500.0, 1500.0], vec![16.0, 0.0, -16.0]),
+            (vec![1100.0, 1500.0], vec![29.09, -29.09]),
+            (vec![1500.0], vec![0.0]),
+        ];
+        for (ratings, expected) in cases {
+            let changes = elo(&ratings);
+            for (change, expected) in changes.iter().zip(&expected) {
+                assert!((change - expected).abs() < 0.01, "{:?}", changes);
+            }
+        }
+    }
+
+    #[test]
+    fn test_leaderboard() {
+        let mut ledger = Ledger::in_memory();
+        let names = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+        for _ in 0..3 {
+            ledger.record(Record::Game {
+                table: 0,
+                standings: names(&["bot-v2", "bot-v1", "random"]),
+            });
+        }
+        for net in &[4, -2, 6, 0] {
+            ledger.record(Record::Hand {
+                table: 1,
+                big_blind: 2,
+                results: vec![
+                    (String::from("bot-v2"), *net),
+                    (String::from("random"), -net),
+                ],
+            });
+        }
+
+        let leaderboard = ledger.leaderboard();
+        let order = leaderboard.players.iter().map(|r| r.name.as_str());
+        assert_eq!(
+            order.collect::<Vec<_>>(),
+            vec!["bot-v2", "bot-v1", "random"]
+        );
+        let best = &leaderboard.players[0];
+        assert_eq!((best.games, best.hands), (3, 4));
+        // won 2, -1, 3 and 0 big blinds
+        assert!((best.winrate - 100.0).abs() < 1e-9);
+        let variance = (1.0 + 4.0 + 4.0 + 1.0) / 3.0;
+        let interval = best.interval.unwrap();
+        assert!((interval - 100.0 * 1.96 * (variance / 4.0f64).sqrt()).abs() < 1e-9);
+        assert_eq!(leaderboard.players[1].interval, None);
+    }
+}
@@ -1,7 +1,39 @@
+#[macro_use]
+mod log;
+
+mod accounts;
 mod api;
+mod cash;
+mod client;
 mod communication;
+mod config;
 mod engine;
+mod equity;
+mod ledger;
+mod lobby;
+mod protocol;
+#[cfg(feature = "python")]
+mod python;
+mod schema;
 mod score;
+mod state;
+mod status;
+mod tournament;
+mod transport;
 
-pub use api::run_server;
-pub use communication::{GameType, Message, PlayerAction, RequestTable, Response, TableRequest};
+pub use accounts::Accounts;
+pub use api::{run_server, Shutdown};
+pub use client::{Bot, Client, ClientError};
+pub use communication::{
+    Action, Ante, BlindLevel, BlindSchedule, BuyIn, Card, Event, Format, GameType, Leaderboard,
+    LevelUp, Lobby, Login, LoginError, LoginReply, Message, PlayerAction, Pot, Ranking, Reply,
+    RequestTable, Response, SeatState, SeatStatus, StateSnapshot, Straddle, Street, Suit,
+    TableInfo, TableRequest, TableStatus,
+};
+pub use config::{Config, Limits, Logging, Timeouts};
+pub use equity::{equity, EquityError};
+pub use ledger::Ledger;
+pub use log::init as init_log;
+pub use protocol::{Capability, Frame, Protocol, PROTOCOL_VERSION};
+pub use schema::schema as protocol_schema;
+pub use state::GameState;
@@ -0,0 +1,338 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use crate::communication::{Event, Lobby, Money, TableInfo, TableRequest, TableStatus};
+
+// the number of hands kept in the recent results
+const RECENT_RESULTS: usize = 20;
+// the hand ids of a server run follow the second it started times this, so
+// they do not repeat after a restart unless a run dealt more hands than this
+// for every second it ran
+const HANDS_PER_SECOND: usize = 1_000_000;
+
+/// A spectator of a table, receiving messages with the time they were sent.
+pub type Watcher = Sender<(Instant, Event)>;
+
+/// All tables the server is forming or running, shared between the
+/// dispatcher that opens them, the tables that report how they are doing and
+/// the lobby that lists them.
+#[derive(Default)]
+pub struct Tables {
+    next_id: usize,
+    tables: BTreeMap<usize, Entry>,
+    // clients in the lobby that have not picked a table yet
+    lobby_clients: usize,
+    // the last hands played at any table, the most recent first
+    results: VecDeque<HandResult>,
+    // the id of the last hand dealt at any table
+    last_hand: usize,
+    // set when the server shuts down, tables stop after the current round
+    closing: bool,
+}
+
+/// The outcome of a hand, as shown on the status page.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct HandResult {
+    pub table: usize,
+    pub hand: usize,
+    pub pot: Money,
+    pub winners: Vec<usize>,
+}
+
+struct Entry {
+    request: TableRequest,
+    status: TableStatus,
+    seated: usize,
+    started: Instant,
+    hands: u64,
+    pots: u64,
+    // where running tables pick up new spectators
+    watchers: Vec<Sender<Watcher>>,
+}
+
+impl Tables {
+    /// The listing of a server that starts now, numbering its hands after
+    /// those of earlier runs.
+    pub fn new() -> Tables {
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs() as usize);
+        Tables {
+            last_hand: started.saturating_mul(HANDS_PER_SECOND),
+            ..Tables::default()
+        }
+    }
+
+    /// Add a table to the listing.
+    /// Returns: the id of the new table
+    pub fn open(&mut self, request: TableRequest, status: TableStatus) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.tables.insert(
+            id,
+            Entry {
+                request,
+                status,
+                seated: 0,
+                started: Instant::now(),
+                hands: 0,
+                pots: 0,
+                watchers: Vec::new(),
+            },
+        );
+        id
+    }
+
+    /// Mark a forming table as running. Its players are counted again as
+    /// they are seated.
+    pub fn start(&mut self, id: usize) {
+        if let Some(table) = self.tables.get_mut(&id) {
+            table.status = TableStatus::Running;
+            table.seated = 0;
+            table.started = Instant::now();
+        }
+    }
+
+    pub fn close(&mut self, id: usize) {
+        self.tables.remove(&id);
+    }
+
+    pub fn set_seated(&mut self, id: usize, seated: usize) {
+        if let Some(table) = self.tables.get_mut(&id) {
+            table.seated = seated;
+        }
+    }
+
+    /// Let a spectator watch a running table.
+    /// Returns: whether the table picked up the spectator
+    pub fn watch(&mut self, id: usize, watcher: Watcher) -> bool {
+        match self.tables.get_mut(&id) {
+            Some(table) => {
+                table.watchers.retain(|tx| tx.send(watcher.clone()).is_ok());
+                !table.watchers.is_empty()
+            }
+            None => false,
+        }
+    }
+
+    /// The request and status of a table, if it exists.
+    pub fn get(&self, id: usize) -> Option<(&TableRequest, TableStatus)> {
+        self.tables.get(&id).map(|t| (&t.request, t.status))
+    }
+
+    /// Ask all tables to stop after the current round.
+    pub fn shut_down(&mut self) {
+        self.closing = true;
+    }
+
+    /// Whether there are no forming or running tables.
+    pub fn is_empty(&self) -> bool {
+        self.tables.is_empty()
+    }
+
+    pub fn set_lobby_clients(&mut self, clients: usize) {
+        self.lobby_clients = clients;
+    }
+
+    pub fn lobby_clients(&self) -> usize {
+        self.lobby_clients
+    }
+
+    /// The last hands played, the most recent first.
+    pub fn results(&self) -> Vec<HandResult> {
+        self.results.iter().cloned().collect()
+    }
+
+    /// The listing of all tables with their statistics.
+    pub fn lobby(&self) -> Lobby {
+        Lobby {
+            tables: self
+                .tables
+                .iter()
+                .map(|(&id, table)| {
+                    let seconds = table.started.elapsed().as_secs_f64().max(1.0);
+                    TableInfo {
+                        id,
+                        request: table.request.clone(),
+                        status: table.status,
+                        seated: table.seated,
+                        seats: table.request.n_players,
+                        average_pot: table.pots.checked_div(table.hands).unwrap_or(0) as Money,
+                        hands_per_hour: (table.hands as f64 * 3600.0 / seconds) as u32,
+                    }
+                })
+                .collect(),
+        }
+    }
+}
+
+/// The part of the listing a single running table updates.
+#[derive(Clone)]
+pub struct TableHandle {
+    pub id: usize,
+    pub tables: Arc<Mutex<Tables>>,
+}
+
+impl TableHandle {
+    fn update<F>(&self, update: F)
+    where
+        F: FnOnce(&mut Entry),
+    {
+        if let Some(table) = self.tables.lock().unwrap().tables.get_mut(&self.id) {
+            update(table);
+        }
+    }
+
+    /// Start taking spectators for this table.
+    /// Returns: where new spectators arrive
+    pub fn watchers(&self) -> Receiver<Watcher> {
+        let (tx, rx) = channel();
+        self.update(|table| table.watchers.push(tx));
+        rx
+    }
+
+    pub fn joined(&self) {
+        self.update(|table| table.seated += 1);
+    }
+
+    pub fn left(&self) {
+        self.update(|table| table.seated = table.seated.saturating_sub(1));
+    }
+
+    /// Give a new hand an id, unique among all tables and, with a listing
+    /// made by `Tables::new`, across restarts of the server.
+    pub fn next_hand(&self) -> usize {
+        let mut tables = self.tables.lock().unwrap();
+        tables.last_hand += 1;
+        tables.last_hand
+    }
+
+    pub fn hand_played(&self, hand: usize, pot: Money, winners: Vec<usize>) {
+        self.update(|table| {
+            table.hands += 1;
+            table.pots += pot as u64;
+        });
+        let mut tables = self.tables.lock().unwrap();
+        tables.results.push_front(HandResult {
+            table: self.id,
+            hand,
+            pot,
+            winners,
+        });
+        tables.results.truncate(RECENT_RESULTS);
+    }
+
+    /// Whether the table should stop after the current round.
+    pub fn closing(&self) -> bool {
+        self.tables.lock().unwrap().closing
+    }
+
+    /// Remove the table from the listing.
+    pub fn close(&self) {
+        self.tables.lock().unwrap().close(self.id);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::communication::{Ante, Format, GameType, Message, Straddle};
+
+    #[test]
+    fn test_tables() {
+        let request = TableRequest {
+            n_players: 6,
+            small_blind: 1,
+            big_blind: 2,
+            stack: 100,
+            game_type: GameType::NoLimit,
+            ante: Ante::None,
+            straddle: Straddle::None,
+            format: Format::Cash,
+        };
+        let tables = Arc::new(Mutex::new(Tables::default()));
+        let forming = tables
+            .lock()
+            .unwrap()
+            .open(request.clone(), TableStatus::Forming);
+        let running = tables
+            .lock()
+            .unwrap()
+            .open(request.clone(), TableStatus::Forming);
+        assert_ne!(forming, running);
+
+        tables.lock().unwrap().set_seated(forming, 2);
+        tables.lock().unwrap().start(running);
+        let handle = TableHandle {
+            id: running,
+            tables: tables.clone(),
+        };
+        for _ in 0..6 {
+            handle.joined();
+        }
+        handle.left();
+        // hand ids are unique among all tables
+        let other = TableHandle {
+            id: forming,
+            tables: tables.clone(),
+        };
+        let ids = (handle.next_hand(), other.next_hand(), handle.next_hand());
+        assert_eq!(ids, (1, 2, 3));
+        // a server that starts later numbers its hands after these
+        let restarted = TableHandle {
+            id: 0,
+            tables: Arc::new(Mutex::new(Tables::new())),
+        };
+        let first = restarted.next_hand();
+        assert!(first > 1_700_000_000 * HANDS_PER_SECOND, "{}", first);
+        assert_eq!(restarted.next_hand(), first + 1);
+        handle.hand_played(1, 10, vec![0]);
+        handle.hand_played(2, 21, vec![1, 2]);
+
+        let lobby = tables.lock().unwrap().lobby();
+        assert_eq!(lobby.tables.len(), 2);
+        let info = &lobby.tables[0];
+        assert_eq!(
+            (info.id, info.status, info.seated, info.seats),
+            (forming, TableStatus::Forming, 2, 6)
+        );
+        let info = &lobby.tables[1];
+        assert_eq!(
+            (info.id, info.status, info.seated, info.average_pot),
+            (running, TableStatus::Running, 5, 15)
+        );
+        assert!(info.hands_per_hour > 0);
+        let results = tables.lock().unwrap().results();
+        assert_eq!(
+            results.iter().map(|r| r.pot).collect::<Vec<_>>(),
+            vec![21, 10]
+        );
+
+        // only tables that take spectators can be watched
+        let (tx, rx) = channel();
+        assert!(!tables.lock().unwrap().watch(forming, tx.clone()));
+        let watchers = handle.watchers();
+        assert!(tables.lock().unwrap().watch(running, tx));
+        let watcher = watchers.try_recv().unwrap();
+        let event = Event {
+            hand: 1,
+            seq: 2,
+            message: Message::GameOver,
+        };
+        watcher.send((Instant::now(), event)).unwrap();
+        assert_eq!(rx.try_recv().unwrap().1.seq, 2);
+
+        assert!(!handle.closing());
+        tables.lock().unwrap().shut_down();
+        assert!(handle.closing());
+
+        handle.close();
+        assert_eq!(tables.lock().unwrap().lobby().tables.len(), 1);
+        assert!(tables.lock().unwrap().get(running).is_none());
+        tables.lock().unwrap().close(forming);
+        assert!(tables.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,52 @@
+use std::fmt::Arguments;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::sync::Mutex;
+
+use crate::config::Logging;
+
+/// Write a line to the server log, with the arguments of `println!`.
+macro_rules! log {
+    ($($arg:tt)*) => {
+        $crate::log::write(format_args!($($arg)*))
+    };
+}
+
+enum Target {
+    Stdout,
+    File(File),
+    Nowhere,
+}
+
+static TARGET: Mutex<Target> = Mutex::new(Target::Stdout);
+
+/// Send the log where the configuration asks for, it goes to stdout until
+/// this is called.
+pub fn init(logging: &Logging) -> io::Result<()> {
+    let target = match &logging.file {
+        _ if logging.quiet => Target::Nowhere,
+        Some(path) => Target::File(OpenOptions::new().create(true).append(true).open(path)?),
+        None => Target::Stdout,
+    };
+    *TARGET.lock().unwrap() = target;
+    Ok(())
+}
+
+/// Make sure everything logged so far is written.
+pub fn flush() {
+    match &mut *TARGET.lock().unwrap() {
+        Target::Stdout => io::stdout().flush().ok(),
+        Target::File(file) => file.sync_all().ok(),
+        Target::Nowhere => None,
+    };
+}
+
+pub fn write(line: Arguments) {
+    match &mut *TARGET.lock().unwrap() {
+        Target::Stdout => println!("{}", line),
+        Target::File(file) => {
+            writeln!(file, "{}", line).ok();
+        }
+        Target::Nowhere => {}
+    }
+}
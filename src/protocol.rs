@@ -0,0 +1,329 @@
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::communication::{Card, Event, LoginError, Message, Reply, Response, Suit};
+
+/// The newest version of the protocol the server speaks. Clients that do not
+/// announce a version speak version 1: every table message on its own,
+/// without the event that numbers it, and every action broadcast. Messages
+/// added in later versions are sent to them as well.
+pub const PROTOCOL_VERSION: u32 = 3;
+
+// the first version that wraps table messages in events
+pub(crate) const EVENTS_VERSION: u32 = 3;
+
+// the capabilities this server can serve
+const SUPPORTED: &[Capability] = &[
+    Capability::CompactCards,
+    Capability::ActionBroadcasts,
+    Capability::StateSnapshots,
+    Capability::MessagePack,
+];
+
+/// Optional features of the protocol a client can ask for when logging in.
+#[derive(Serialize, Deserialize, JsonSchema, PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Capability {
+    // cards are sent as a rank and a suit character, like "Ah" or "Td",
+    // instead of objects
+    CompactCards,
+    // the actions of every player are sent as they happen, without it the
+    // client only learns about them through the bets in its requests to act
+    ActionBroadcasts,
+    // a StateSnapshot is sent when sitting down and before every request to
+    // act. Snapshots can be asked for without it
+    StateSnapshots,
+    // after the login reply, the server sends MessagePack in binary frames
+    // instead of JSON in text frames, to spectators as well
+    MessagePack,
+}
+
+/// A frame of a connection: JSON text or binary MessagePack.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum Frame {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+impl Frame {
+    /// Read a message from a frame, whichever format it is in. Clients can
+    /// send either, whatever they agreed on.
+    pub fn decode<T: DeserializeOwned>(&self) -> Option<T> {
+        match self {
+            Frame::Text(text) => serde_json::from_str(text).ok(),
+            Frame::Binary(bytes) => rmp_serde::from_slice(bytes).ok(),
+        }
+    }
+
+    /// Read a response to a table message, either bare or as a reply.
+    /// Returns: the response, with the sequence number of the message it
+    /// answers if the client named it
+    pub fn decode_response(&self) -> Option<(Option<u64>, Response)> {
+        match self.decode::<Reply>() {
+            Some(reply) => Some((Some(reply.to), reply.response)),
+            None => Some((None, self.decode::<Response>()?)),
+        }
+    }
+}
+
+/// The protocol the server and a client agreed on when logging in.
+#[derive(Serialize, Deserialize, JsonSchema, PartialEq, Eq, Clone, Debug)]
+pub struct Protocol {
+    pub version: u32,
+    pub capabilities: Vec<Capability>,
+}
+
+impl Default for Protocol {
+    /// Version 1, table messages without events and every action broadcast.
+    fn default() -> Protocol {
+        Protocol {
+            version: 1,
+            capabilities: vec![Capability::ActionBroadcasts],
+        }
+    }
+}
+
+impl Protocol {
+    /// Agree on the newest version both sides speak, with the capabilities
+    /// the client asked for that the server supports.
+    pub fn negotiate(version: u32, capabilities: &[Capability]) -> Result<Protocol, LoginError> {
+        match version {
+            0 => Err(LoginError::UnsupportedVersion),
+            1 => Ok(Protocol::default()),
+            _ => Ok(Protocol {
+                version: version.min(PROTOCOL_VERSION),
+                capabilities: SUPPORTED
+                    .iter()
+                    .filter(|c| capabilities.contains(c))
+                    .cloned()
+                    .collect(),
+            }),
+        }
+    }
+
+    pub fn has(&self, capability: Capability) -> bool {
+        self.capabilities.contains(&capability)
+    }
+
+    /// Write a table message the way the client asked for it, as an event
+    /// for clients that know them.
+    /// Returns: the frame to send, None if the client does not want the
+    /// message
+    pub fn encode_event(&self, event: &Event<&Message>) -> Option<Frame> {
+        if let Message::PlayerActed { .. } = event.message {
+            if !self.has(Capability::ActionBroadcasts) {
+                return None;
+            }
+        }
+        match self.version < EVENTS_VERSION {
+            true => Some(self.encode(event.message)),
+            false => Some(self.encode(event)),
+        }
+    }
+
+    /// Write any message from the server the way the client asked for it.
+    pub fn encode<T: Serialize>(&self, message: &T) -> Frame {
+        let binary = self.has(Capability::MessagePack);
+        if !self.has(Capability::CompactCards) {
+            return match binary {
+                true => Frame::Binary(rmp_serde::to_vec_named(message).unwrap()),
+                false => Frame::Text(serde_json::to_string(message).unwrap()),
+            };
+        }
+        let mut value = serde_json::to_value(message).unwrap();
+        compact_cards(&mut value);
+        match binary {
+            true => Frame::Binary(rmp_serde::to_vec_named(&value).unwrap()),
+            false => Frame::Text(value.to_string()),
+        }
+    }
+}
+
+/// Replace every card in a message by its short form.
+pub(crate) fn compact_cards(value: &mut Value) {
+    let is_card = value.as_object().is_some_and(|object| {
+        object.len() == 2 && object.contains_key("rank") && object.contains_key("suit")
+    });
+    if is_card {
+        if let Ok(card) = Card::deserialize(&*value) {
+            *value = Value::String(compact(card));
+            return;
+        }
+    }
+    match value {
+        Value::Object(object) => object.values_mut().for_each(compact_cards),
+        Value::Array(values) => values.iter_mut().for_each(compact_cards),
+        _ => {}
+    }
+}
+
+/// A card as its rank and suit, like "Ah" for the ace of hearts.
+pub(crate) fn compact(card: Card) -> String {
+    let rank = match card.rank {
+        10 => 'T',
+        11 => 'J',
+        12 => 'Q',
+        13 => 'K',
+        14 | 1 => 'A',
+        rank => (b'0' + rank) as char,
+    };
+    let suit = match card.suit {
+        Suit::Hearts => 'h',
+        Suit::Spades => 's',
+        Suit::Clubs => 'c',
+        Suit::Diamonds => 'd',
+    };
+    format!("{}{}", rank, suit)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::communication::Action;
+
+    #[test]
+    fn test_negotiate() {
+        use Capability::*;
+        // (version, capabilities asked for, agreed protocol)
+        let cases = vec![
+            (0, vec![], Err(LoginError::UnsupportedVersion)),
+            (1, vec![CompactCards], Ok(Protocol::default())),
+            (
+                2,
+                vec![StateSnapshots, CompactCards],
+                Ok(Protocol {
+                    version: 2,
+                    capabilities: vec![CompactCards, StateSnapshots],
+                }),
+            ),
+            (
+                7,
+                vec![],
+                Ok(Protocol {
+                    version: PROTOCOL_VERSION,
+                    capabilities: vec![],
+                }),
+            ),
+        ];
+        for (version, capabilities, expected) in cases {
+            assert_eq!(Protocol::negotiate(version, &capabilities), expected);
+        }
+    }
+
+    #[test]
+    fn test_encode() {
+        let card = |rank, suit| Card { rank, suit };
+        let flop = Message::Flop(
+            card(14, Suit::Hearts),
+            card(10, Suit::Diamonds),
+            card(2, Suit::Clubs),
+        );
+        let acted = Message::PlayerActed {
+            player: 0,
+            action: Action::Call,
+            amount: 2,
+            stack: 98,
+        };
+
+        let event = |message| Event {
+            hand: 7,
+            seq: 42,
+            message,
+        };
+        let text = |text: &str| Some(Frame::Text(String::from(text)));
+        let legacy = Protocol::default();
+        assert_eq!(
+            legacy.encode_event(&event(&flop)),
+            Some(Frame::Text(serde_json::to_string(&flop).unwrap()))
+        );
+        assert!(legacy.encode_event(&event(&acted)).is_some());
+
+        let compact = Protocol::negotiate(2, &[Capability::CompactCards]).unwrap();
+        assert_eq!(
+            compact.encode_event(&event(&flop)),
+            text(r#"{"Flop":["Ah","Td","2c"]}"#)
+        );
+        let hole = Message::Hole {
+            player: 1,
+            cards: (card(13, Suit::Spades), card(9, Suit::Hearts)),
+        };
+        assert_eq!(
+            compact.encode_event(&event(&hole)),
+            text(r#"{"Hole":{"cards":["Ks","9h"],"player":1}}"#)
+        );
+        assert_eq!(compact.encode_event(&event(&acted)), None);
+
+        // binary frames read back as the same message, compact cards as the
+        // same strings
+        let binary = Protocol::negotiate(2, &[Capability::MessagePack]).unwrap();
+        let frame = binary.encode_event(&event(&flop)).unwrap();
+        assert!(matches!(frame, Frame::Binary(_)));
+        let decoded = frame.decode::<Message>().unwrap();
+        assert_eq!(format!("{:?}", decoded), format!("{:?}", flop));
+        let both =
+            Protocol::negotiate(2, &[Capability::MessagePack, Capability::CompactCards]).unwrap();
+        let decoded = both.encode_event(&event(&flop)).unwrap().decode::<Value>();
+        assert_eq!(
+            decoded,
+            Some(serde_json::json!({"Flop": ["Ah", "Td", "2c"]}))
+        );
+
+        // newer clients get the hand and the sequence number with it
+        let events = Protocol::negotiate(3, &[Capability::CompactCards]).unwrap();
+        assert_eq!(
+            events.encode_event(&event(&flop)),
+            text(r#"{"hand":7,"message":{"Flop":["Ah","Td","2c"]},"seq":42}"#)
+        );
+        assert_eq!(events.encode_event(&event(&acted)), None);
+    }
+
+    #[test]
+    fn test_decode() {
+        use crate::communication::PlayerAction;
+        let raise = Response::Action(PlayerAction::Raise(8));
+        let frames = vec![
+            Frame::Text(serde_json::to_string(&raise).unwrap()),
+            Frame::Binary(rmp_serde::to_vec_named(&raise).unwrap()),
+            Frame::Binary(rmp_serde::to_vec(&raise).unwrap()),
+        ];
+        for frame in frames {
+            match frame.decode::<Response>() {
+                Some(Response::Action(PlayerAction::Raise(8))) => {}
+                response => panic!("{:?} decoded as {:?}", frame, response),
+            }
+        }
+        assert!(Frame::Binary(vec![0xc1]).decode::<Response>().is_none());
+
+        // responses can name the message they answer
+        // (frame, sequence number named)
+        let cases = vec![
+            (Frame::Text(String::from(r#"{"Action":{"Raise":8}}"#)), None),
+            (
+                Frame::Text(String::from(
+                    r#"{"to":12,"response":{"Action":{"Raise":8}}}"#,
+                )),
+                Some(12),
+            ),
+            (
+                Frame::Binary(
+                    rmp_serde::to_vec_named(&Reply {
+                        to: 3,
+                        response: Response::Action(PlayerAction::Raise(8)),
+                    })
+                    .unwrap(),
+                ),
+                Some(3),
+            ),
+        ];
+        for (frame, to) in cases {
+            match frame.decode_response() {
+                Some((seq, Response::Action(PlayerAction::Raise(8)))) => assert_eq!(seq, to),
+                response => panic!("{:?} decoded as {:?}", frame, response),
+            }
+        }
+        assert!(Frame::Text(String::from(r#"{"to":12}"#))
+            .decode_response()
+            .is_none());
+    }
+}
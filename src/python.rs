@@ -0,0 +1,316 @@
+//! Python bindings, built with the `python` feature. Cards are written like
+//! "Ah" for the ace of hearts, table messages and states are the dictionaries
+//! the server sends as JSON, with cards written the same way.
+//!
+//! ```python
+//! import poker
+//!
+//! class Caller:
+//!     def act(self, state):
+//!         return "Call"  # or "Fold", or {"Raise": 10}
+//!
+//! poker.evaluate(["Ah", "Kh", "Qh", "Jh", "Th", "2c", "3d"])
+//! poker.equity([("Ah", "As"), ("Kh", "Ks")], board=["2c", "7d", "9c"])
+//! poker.Table([Caller(), Caller()], stack=100).play(hands=10)
+//! ```
+// the functions pyo3 generates convert errors to themselves
+#![allow(clippy::useless_conversion)]
+
+use pyo3::basic::CompareOp;
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use serde::Serialize;
+
+use crate::communication::{
+    Ante, Callback, Card, Error, GameType, Message, Money, PlayerAction, Response, Straddle, Suit,
+};
+use crate::engine::Table as Engine;
+use crate::protocol::compact_cards;
+use crate::score::Score as Evaluation;
+use crate::state::GameState;
+
+/// The value of the best five cards out of seven. Scores compare like the
+/// hands they are made of.
+#[pyclass(frozen)]
+struct Score(Evaluation);
+
+#[pymethods]
+impl Score {
+    fn __richcmp__(&self, other: &Score, op: CompareOp) -> bool {
+        op.matches(self.0.cmp(&other.0))
+    }
+
+    fn __repr__(&self) -> String {
+        format!("{:?}", self.0)
+    }
+}
+
+/// Score the best hand out of the hole cards and the five cards on the board.
+#[pyfunction]
+fn evaluate(cards: Vec<String>) -> PyResult<Score> {
+    if cards.len() != 7 {
+        return Err(PyValueError::new_err("a hand is scored from seven cards"));
+    }
+    Ok(Score(Evaluation::calculate(parse_cards(&cards)?)))
+}
+
+/// The share of the pot every hand wins on average when the rest of the
+/// board is dealt, counted exactly when there are at most `samples` ways to
+/// deal it and sampled otherwise.
+#[pyfunction]
+#[pyo3(signature = (hands, board=Vec::new(), samples=10000))]
+fn equity(hands: Vec<(String, String)>, board: Vec<String>, samples: usize) -> PyResult<Vec<f64>> {
+    let hands = hands
+        .iter()
+        .map(|(c1, c2)| Ok((parse_card(c1)?, parse_card(c2)?)))
+        .collect::<PyResult<Vec<_>>>()?;
+    crate::equity::equity(&hands, &parse_cards(&board)?, samples)
+        .map_err(|e| PyValueError::new_err(format!("{:?}", e)))
+}
+
+fn parse_card(text: &str) -> PyResult<Card> {
+    parse_compact(text).ok_or_else(|| PyValueError::new_err(format!("not a card: {:?}", text)))
+}
+
+fn parse_cards(texts: &[String]) -> PyResult<Vec<Card>> {
+    texts.iter().map(|text| parse_card(text)).collect()
+}
+
+/// Read a card written like the server writes it. Tens may be written as 10.
+fn parse_compact(text: &str) -> Option<Card> {
+    let suit = text.chars().last()?;
+    let rank = &text[..text.len() - suit.len_utf8()];
+    let rank = match rank {
+        "T" | "t" | "10" => 10,
+        "J" | "j" => 11,
+        "Q" | "q" => 12,
+        "K" | "k" => 13,
+        "A" | "a" => 14,
+        rank => match rank.parse() {
+            Ok(rank) if (2..10).contains(&rank) => rank,
+            _ => return None,
+        },
+    };
+    let suit = match suit.to_ascii_lowercase() {
+        'h' => Suit::Hearts,
+        's' => Suit::Spades,
+        'c' => Suit::Clubs,
+        'd' => Suit::Diamonds,
+        _ => return None,
+    };
+    Some(Card { rank, suit })
+}
+
+/// A no-limit table played in-process by Python bots, one for every seat.
+/// A bot has an `act(state)` method that returns the action to take, and
+/// may have a `straddle(state, amount)` method that says whether to
+/// straddle and an `observe(state, message)` method that sees every message
+/// of the table the bot may see.
+#[pyclass]
+struct Table {
+    bots: Vec<PyObject>,
+    stack: Money,
+    small_blind: Money,
+    big_blind: Money,
+    straddle: bool,
+}
+
+#[pymethods]
+impl Table {
+    #[new]
+    #[pyo3(signature = (bots, stack=1000, small_blind=1, big_blind=2, straddle=false))]
+    fn new(
+        bots: Vec<PyObject>,
+        stack: Money,
+        small_blind: Money,
+        big_blind: Money,
+        straddle: bool,
+    ) -> PyResult<Table> {
+        if bots.len() < 2 {
+            return Err(PyValueError::new_err("a table needs at least two bots"));
+        }
+        Ok(Table {
+            bots,
+            stack,
+            small_blind,
+            big_blind,
+            straddle,
+        })
+    }
+
+    /// Play a number of hands and return the stack of every seat. When one
+    /// bot has won all the chips, every bot gets its stack back and play
+    /// goes on.
+    fn play(&self, py: Python, hands: usize) -> PyResult<Vec<Money>> {
+        let stacks = vec![self.stack; self.bots.len()];
+        let mut bots = Bots {
+            py,
+            bots: self.bots.iter().map(|bot| bot.bind(py).clone()).collect(),
+            state: GameState::new(stacks.clone()),
+            hands,
+            stacks: stacks.clone(),
+            error: None,
+        };
+        let straddle = match self.straddle {
+            true => Straddle::Voluntary,
+            false => Straddle::None,
+        };
+        let finished = Engine::new(
+            GameType::NoLimit,
+            self.small_blind,
+            self.big_blind,
+            Ante::None,
+            straddle,
+            stacks,
+            &mut bots,
+        )
+        .play();
+        if let Some(error) = bots.error {
+            return Err(error);
+        }
+        if !finished {
+            return Err(PyRuntimeError::new_err("the table was aborted"));
+        }
+        Ok(bots.stacks)
+    }
+}
+
+/// The callback of a table with Python bots. Once a bot fails, the others
+/// are not asked anything anymore and the table stops after the round.
+struct Bots<'py> {
+    py: Python<'py>,
+    bots: Vec<Bound<'py, PyAny>>,
+    state: GameState,
+    // how many hands are left to start
+    hands: usize,
+    // the stacks once the table stopped
+    stacks: Vec<Money>,
+    error: Option<PyErr>,
+}
+
+impl<'py> Bots<'py> {
+    /// Show the bots the message and ask the bot it is for what to do.
+    fn respond(&self, message: &Message) -> PyResult<Response> {
+        for (player, bot) in self.bots.iter().enumerate() {
+            let hidden = matches!(message, Message::Hole { player: owner, .. } if *owner != player);
+            if !hidden && bot.hasattr("observe")? {
+                let state = self.to_python(&self.state.snapshot(player))?;
+                bot.call_method1("observe", (state, self.to_python(message)?))?;
+            }
+        }
+        match *message {
+            Message::RequestAction { player, .. } => {
+                let state = self.to_python(&self.state.snapshot(player))?;
+                let action = self.bots[player].call_method1("act", (state,))?;
+                Ok(Response::Action(self.action(&action)?))
+            }
+            Message::RequestStraddle { player, amount } => {
+                let bot = &self.bots[player];
+                if !bot.hasattr("straddle")? {
+                    return Ok(Response::Straddle(false));
+                }
+                let state = self.to_python(&self.state.snapshot(player))?;
+                let straddle = bot.call_method1("straddle", (state, amount))?;
+                Ok(Response::Straddle(straddle.is_truthy()?))
+            }
+            _ => Ok(Response::Ack),
+        }
+    }
+
+    /// A value as the dictionary it is sent to clients as.
+    fn to_python<T: Serialize>(&self, value: &T) -> PyResult<Bound<'py, PyAny>> {
+        let mut value = serde_json::to_value(value).unwrap();
+        compact_cards(&mut value);
+        self.py
+            .import_bound("json")?
+            .call_method1("loads", (value.to_string(),))
+    }
+
+    /// An action given as the dictionary it is sent by clients as.
+    fn action(&self, action: &Bound<'py, PyAny>) -> PyResult<PlayerAction> {
+        let text = self
+            .py
+            .import_bound("json")?
+            .call_method1("dumps", (action,))?
+            .extract::<String>()?;
+        serde_json::from_str(&text)
+            .map_err(|_| PyValueError::new_err(format!("not an action: {}", text)))
+    }
+}
+
+impl Callback for &mut Bots<'_> {
+    fn callback(&mut self, message: Message) -> Result<Response, Error> {
+        if self.state.begins_hand(&message) {
+            self.hands -= 1;
+            self.state.start_hand(self.state.hand + 1);
+        }
+        self.state.apply(&message);
+        match &message {
+            Message::Shutdown { stacks } => self.stacks = stacks.clone(),
+            Message::Error(error) if self.error.is_none() => {
+                let text = format!("player {}: {:?}", error.player, error.error);
+                self.error = Some(PyRuntimeError::new_err(text));
+            }
+            _ => {}
+        }
+        if self.error.is_none() {
+            match self.respond(&message) {
+                Ok(response) => return Ok(response),
+                Err(error) => self.error = Some(error),
+            }
+        }
+        // fold the rest of the round
+        Ok(match message {
+            Message::RequestAction { .. } => Response::Action(PlayerAction::Fold),
+            Message::RequestStraddle { .. } => Response::Straddle(false),
+            _ => Response::Ack,
+        })
+    }
+
+    fn stopping(&mut self) -> bool {
+        self.hands == 0 || self.error.is_some()
+    }
+}
+
+#[pymodule]
+fn poker(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<Score>()?;
+    module.add_class::<Table>()?;
+    module.add_function(wrap_pyfunction!(evaluate, module)?)?;
+    module.add_function(wrap_pyfunction!(equity, module)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::protocol::compact;
+    use num_traits::FromPrimitive;
+
+    #[test]
+    fn test_parse_compact() {
+        let card = |rank, suit| Some(Card { rank, suit });
+        // (text, expected)
+        let cases = vec![
+            ("Ah", card(14, Suit::Hearts)),
+            ("Td", card(10, Suit::Diamonds)),
+            ("10d", card(10, Suit::Diamonds)),
+            ("2C", card(2, Suit::Clubs)),
+            ("ks", card(13, Suit::Spades)),
+            ("1h", None),
+            ("Ax", None),
+            ("A", None),
+            ("", None),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(parse_compact(text), expected, "{}", text);
+        }
+        for i in 0..52 {
+            let card = Card {
+                suit: Suit::from_u8(i / 13).unwrap(),
+                rank: 2 + i % 13,
+            };
+            assert_eq!(parse_compact(&compact(card)), Some(card));
+        }
+    }
+}
@@ -0,0 +1,307 @@
+use schemars::gen::{SchemaGenerator, SchemaSettings};
+use schemars::JsonSchema;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+
+use crate::communication::{
+    Action, Ante, BlindLevel, Card, Event, Format, GameType, Leaderboard, Lobby, Login, LoginError,
+    LoginReply, Message, PlayerAction, Pot, Ranking, Reply, RequestTable, Response, SeatState,
+    SeatStatus, StateSnapshot, Straddle, Street, Suit, TableInfo, TableRequest, TableStatus,
+};
+use crate::protocol::{Capability, Protocol, PROTOCOL_VERSION};
+use crate::score::Score;
+
+/// The JSON Schema (draft 7) of the protocol. `ClientMessage` and
+/// `ServerMessage` in its definitions are everything that can be sent either
+/// way, the root accepts both. Every message type comes with examples.
+/// Cards are given as objects, clients that asked for compact cards get
+/// strings like "Ah" instead.
+pub fn schema() -> Value {
+    let mut generator = SchemaSettings::draft07().into_generator();
+    let mut examples = Map::new();
+    let client = vec![
+        message::<Login>(&mut generator, &mut examples, login_examples()),
+        message::<RequestTable>(&mut generator, &mut examples, request_examples()),
+        message::<Response>(&mut generator, &mut examples, response_examples()),
+        message::<Reply>(&mut generator, &mut examples, reply_to_examples()),
+    ];
+    let server = vec![
+        message::<LoginReply>(&mut generator, &mut examples, reply_examples()),
+        message::<Lobby>(&mut generator, &mut examples, lobby_examples()),
+        message::<Leaderboard>(&mut generator, &mut examples, leaderboard_examples()),
+        message::<Message>(&mut generator, &mut examples, table_examples()),
+        message::<Event>(&mut generator, &mut examples, event_examples()),
+    ];
+
+    let mut definitions = Map::new();
+    for (name, schema) in generator.take_definitions() {
+        let mut schema = serde_json::to_value(schema).unwrap();
+        if let (Some(examples), Value::Object(schema)) = (examples.remove(&name), &mut schema) {
+            schema.insert(String::from("examples"), examples);
+        }
+        definitions.insert(name, schema);
+    }
+    definitions.insert(String::from("ClientMessage"), json!({ "anyOf": client }));
+    definitions.insert(String::from("ServerMessage"), json!({ "anyOf": server }));
+    json!({
+        "$schema": "http://json-schema.org/draft-07/schema#",
+        "title": format!("Pokerroom protocol version {}", PROTOCOL_VERSION),
+        "description": "Every websocket text frame holds one message, or every binary frame \
+            for clients that asked for MessagePack. Over plain TCP and for bots the server \
+            runs itself, every line holds one JSON message. Clients first send a \
+            Login and get a LoginReply. In the lobby they get a Lobby whenever it changes and \
+            send a RequestTable, asking for the leaderboard gets a Leaderboard. At a table \
+            they get Messages, and answer those that request an action or a straddle with a \
+            Response. From version 3 on every Message comes in an Event with the id of the hand \
+            and a sequence number, and a Response can be sent as a Reply to the sequence number \
+            of the request, so that late replies to earlier requests are dropped. Cards are objects unless the client asked for CompactCards, then they \
+            are a rank and a suit character like \"Ah\" or \"Td\".",
+        "anyOf": [
+            { "$ref": "#/definitions/ClientMessage" },
+            { "$ref": "#/definitions/ServerMessage" },
+        ],
+        "definitions": definitions,
+    })
+}
+
+/// Add a message type to the definitions, and put its examples aside.
+/// Returns: the reference to its definition
+fn message<T: JsonSchema + Serialize>(
+    generator: &mut SchemaGenerator,
+    examples: &mut Map<String, Value>,
+    values: Vec<T>,
+) -> Value {
+    let reference = serde_json::to_value(generator.subschema_for::<T>()).unwrap();
+    examples.insert(T::schema_name(), serde_json::to_value(values).unwrap());
+    reference
+}
+
+fn card(rank: u8, suit: Suit) -> Card {
+    Card { rank, suit }
+}
+
+fn table_request() -> TableRequest {
+    TableRequest {
+        n_players: 6,
+        small_blind: 1,
+        big_blind: 2,
+        stack: 200,
+        game_type: GameType::NoLimit,
+        ante: Ante::None,
+        straddle: Straddle::None,
+        format: Format::Cash,
+    }
+}
+
+fn login_examples() -> Vec<Login> {
+    vec![Login {
+        name: String::from("my-bot"),
+        token: String::from("secret"),
+        version: PROTOCOL_VERSION,
+        capabilities: vec![Capability::CompactCards, Capability::ActionBroadcasts],
+    }]
+}
+
+fn request_examples() -> Vec<RequestTable> {
+    vec![
+        RequestTable::Table(table_request()),
+        RequestTable::Join {
+            table: 3,
+            buy_in: Some(100),
+        },
+        RequestTable::Watch {
+            table: 3,
+            delay: 30,
+        },
+        RequestTable::Leaderboard,
+    ]
+}
+
+fn response_examples() -> Vec<Response> {
+    vec![
+        Response::Action(PlayerAction::Raise(10)),
+        Response::Action(PlayerAction::Call),
+        Response::Ack,
+        Response::TopUp(50),
+        Response::Snapshot,
+    ]
+}
+
+fn reply_to_examples() -> Vec<Reply> {
+    vec![Reply {
+        to: 345,
+        response: Response::Action(PlayerAction::Call),
+    }]
+}
+
+fn event_examples() -> Vec<Event> {
+    vec![Event {
+        hand: 12,
+        seq: 345,
+        message: Message::RequestAction {
+            player: 0,
+            bets: vec![Some(1), None, Some(2)],
+            pot: 3,
+            min_raise: Some(2),
+        },
+    }]
+}
+
+fn reply_examples() -> Vec<LoginReply> {
+    vec![
+        LoginReply::Accepted(Protocol {
+            version: PROTOCOL_VERSION,
+            capabilities: vec![Capability::CompactCards],
+        }),
+        LoginReply::Rejected(LoginError::InvalidCredentials),
+    ]
+}
+
+fn lobby_examples() -> Vec<Lobby> {
+    vec![Lobby {
+        tables: vec![TableInfo {
+            id: 3,
+            request: table_request(),
+            status: TableStatus::Forming,
+            seated: 2,
+            seats: 6,
+            average_pot: 0,
+            hands_per_hour: 0,
+        }],
+    }]
+}
+
+fn leaderboard_examples() -> Vec<Leaderboard> {
+    vec![Leaderboard {
+        players: vec![Ranking {
+            name: String::from("my-bot"),
+            rating: 1516.0,
+            games: 1,
+            hands: 120,
+            winrate: 4.2,
+            interval: Some(1.3),
+            bankroll: 80,
+        }],
+    }]
+}
+
+fn table_examples() -> Vec<Message> {
+    let board = vec![
+        card(14, Suit::Hearts),
+        card(10, Suit::Diamonds),
+        card(2, Suit::Clubs),
+        card(2, Suit::Spades),
+        card(7, Suit::Hearts),
+    ];
+    let mut hand = board.clone();
+    hand.extend(vec![card(14, Suit::Spades), card(13, Suit::Spades)]);
+    vec![
+        Message::Players(vec![
+            Some(String::from("my-bot")),
+            None,
+            Some(String::from("other")),
+        ]),
+        Message::Hole {
+            player: 0,
+            cards: (card(14, Suit::Spades), card(13, Suit::Spades)),
+        },
+        Message::PlayerActed {
+            player: 2,
+            action: Action::BigBlind,
+            amount: 2,
+            stack: 198,
+        },
+        Message::RequestAction {
+            player: 0,
+            bets: vec![Some(1), None, Some(2)],
+            pot: 3,
+            min_raise: Some(2),
+        },
+        Message::Flop(board[0], board[1], board[2]),
+        Message::StateSnapshot(StateSnapshot {
+            player: 0,
+            hand: 12,
+            street: Street::Flop,
+            board: board[..3].to_vec(),
+            pot: 6,
+            seats: vec![
+                SeatState {
+                    name: Some(String::from("my-bot")),
+                    stack: 197,
+                    bet: 0,
+                    status: SeatStatus::Active,
+                },
+                SeatState {
+                    name: None,
+                    stack: 0,
+                    bet: 0,
+                    status: SeatStatus::Empty,
+                },
+                SeatState {
+                    name: Some(String::from("other")),
+                    stack: 187,
+                    bet: 10,
+                    status: SeatStatus::Active,
+                },
+            ],
+            actor: Some(0),
+            min_raise: Some(10),
+            hole: Some((card(14, Suit::Spades), card(13, Suit::Spades))),
+        }),
+        Message::Showdown {
+            score: Score::calculate(hand.clone()),
+            pot: 12,
+            players: vec![0],
+            stacks: vec![206, 0, 194],
+            pots: vec![Pot {
+                amount: 12,
+                players: vec![0],
+                score: Score::calculate(hand),
+            }],
+        },
+        Message::BlindsUp {
+            level: 1,
+            blinds: BlindLevel {
+                small_blind: 2,
+                big_blind: 4,
+                ante: Ante::None,
+            },
+        },
+        Message::GameOver,
+    ]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use jsonschema::JSONSchema;
+
+    #[test]
+    fn test_schema() {
+        let schema = schema();
+        let compiled = JSONSchema::compile(&schema).unwrap();
+        let definitions = schema["definitions"].as_object().unwrap();
+        let mut n_examples = 0;
+        for (name, definition) in definitions {
+            for example in definition["examples"].as_array().into_iter().flatten() {
+                assert!(compiled.is_valid(example), "{}: {}", name, example);
+                n_examples += 1;
+            }
+        }
+        assert!(n_examples > 20);
+        assert!(definitions["Message"]["examples"].is_array());
+
+        // (message, valid)
+        let cases = vec![
+            (json!({"Action": {"Raise": 4}}), true),
+            (json!("GameOver"), true),
+            (json!({"name": "bot", "token": ""}), true),
+            (json!({"Action": {"Raise": -4}}), false),
+            (json!({"Flop": [{"rank": 2, "suit": "Hearts"}]}), false),
+            (json!({"Bet": 4}), false),
+        ];
+        for (message, valid) in cases {
+            assert_eq!(compiled.is_valid(&message), valid, "{}", message);
+        }
+    }
+}
@@ -1,8 +1,11 @@
 use crate::communication::{Card, Suit};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
-#[derive(Ord, Eq, PartialEq, PartialOrd, Default, Clone, Copy, Debug, Serialize, Deserialize)]
+#[derive(
+    Ord, Eq, PartialEq, PartialOrd, Default, Clone, Copy, Debug, Serialize, Deserialize, JsonSchema,
+)]
 pub struct Score {
     royal_flush: bool,
     // aces-high straight flush.
@@ -658,7 +661,7 @@ mod test {
                 suit: Suit::Hearts,
                 rank: 5,
             },
-        ]);;
+        ]);
         assert_eq!(calculated, score);
     }
 }
@@ -0,0 +1,454 @@
+use crate::communication::{
+    Action, Card, Message, Money, SeatState, SeatStatus, StateSnapshot, Street,
+};
+
+/// The state of a table, rebuilt from the messages it sends. The server keeps
+/// one with every hole card to make snapshots from, a client only learns
+/// about its own hole cards.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct GameState {
+    // the id of the hand being played, 0 before the first hand
+    pub hand: usize,
+    pub street: Street,
+    pub board: Vec<Card>,
+    // the chips in the pot, without the bets of the current street
+    pub pot: Money,
+    pub seats: Vec<SeatState>,
+    // the seat that is asked to act, if any
+    pub actor: Option<usize>,
+    // the smallest raise the actor may make, see Message::RequestAction
+    pub min_raise: Option<Money>,
+    // the hole cards of every seat, as far as they are known
+    pub holes: Vec<Option<(Card, Card)>>,
+    // the stacks a game restarts with after a GameOver
+    start: Vec<Money>,
+}
+
+impl GameState {
+    /// A table that has not played a hand yet, with the given stacks.
+    pub fn new(stacks: Vec<Money>) -> GameState {
+        GameState {
+            hand: 0,
+            street: Street::Finished,
+            board: Vec::new(),
+            pot: 0,
+            seats: stacks
+                .iter()
+                .map(|&stack| SeatState {
+                    name: None,
+                    stack,
+                    bet: 0,
+                    status: SeatStatus::Out,
+                })
+                .collect(),
+            actor: None,
+            min_raise: None,
+            holes: vec![None; stacks.len()],
+            start: stacks,
+        }
+    }
+
+    /// Update the state with a message from the table.
+    pub fn apply(&mut self, message: &Message) {
+        if self.begins_hand(message) {
+            self.start_hand(self.hand + 1);
+        }
+        match message {
+            Message::Hole { player, cards } => {
+                self.resize(player + 1);
+                self.holes[*player] = Some(*cards);
+            }
+            Message::PlayerActed {
+                player,
+                action,
+                amount,
+                stack,
+            } => {
+                self.resize(player + 1);
+                let seat = &mut self.seats[*player];
+                seat.stack = *stack;
+                // antes go straight into the pot
+                match action {
+                    Action::Ante => self.pot += amount,
+                    _ => seat.bet += amount,
+                }
+                if *action == Action::Fold {
+                    seat.status = SeatStatus::Folded;
+                } else if *stack == 0 {
+                    seat.status = SeatStatus::AllIn;
+                }
+                if self.actor == Some(*player) {
+                    self.actor = None;
+                    self.min_raise = None;
+                }
+            }
+            Message::Flop(first, second, third) => {
+                self.deal(Street::Flop, &[*first, *second, *third])
+            }
+            Message::River(card) => self.deal(Street::River, &[*card]),
+            Message::Turn(card) => self.deal(Street::Turn, &[*card]),
+            Message::RequestAction {
+                player,
+                bets,
+                pot,
+                min_raise,
+            } => {
+                self.resize(bets.len());
+                self.actor = Some(*player);
+                self.min_raise = *min_raise;
+                self.pot = *pot;
+                for (seat, bet) in self.seats.iter_mut().zip(bets) {
+                    match bet {
+                        Some(bet) => seat.bet = *bet,
+                        None if seat.status == SeatStatus::Active => {
+                            seat.status = SeatStatus::Folded
+                        }
+                        None => {}
+                    }
+                }
+            }
+            Message::RequestStraddle { player, .. } => {
+                self.actor = Some(*player);
+                self.min_raise = None;
+            }
+            Message::Showdown { pot, stacks, .. } => {
+                self.finish(stacks);
+                self.pot = *pot;
+            }
+            Message::GameOver => {
+                let start = self.start.clone();
+                self.finish(&start);
+            }
+            Message::Shutdown { stacks } | Message::Aborted { stacks } => {
+                self.finish(stacks);
+                self.pot = 0;
+            }
+            Message::Players(names) => {
+                self.resize(names.len());
+                for (seat, name) in self.seats.iter_mut().zip(names) {
+                    seat.status = match (name, seat.status) {
+                        (None, _) => SeatStatus::Empty,
+                        (Some(_), SeatStatus::Empty) => SeatStatus::Out,
+                        (Some(_), status) => status,
+                    };
+                    seat.name = name.clone();
+                }
+            }
+            Message::SeatChange { player, stack } => {
+                self.resize(player + 1);
+                self.seats[*player].stack = *stack;
+            }
+            Message::StateSnapshot(snapshot) => self.restore(snapshot),
+            Message::BlindsUp { .. }
+            | Message::Eliminated { .. }
+            | Message::Standings(..)
+            | Message::Seated { .. }
+            | Message::Error(..) => {}
+        }
+    }
+
+    /// The hole cards of the seat that is asked to act.
+    pub fn hole(&self) -> Option<(Card, Card)> {
+        self.holes.get(self.actor?).copied().flatten()
+    }
+
+    /// What the seat that is asked to act has to put in to call.
+    pub fn to_call(&self) -> Money {
+        let bet = |seat: usize| self.seats.get(seat).map_or(0, |seat| seat.bet);
+        let highest = self.seats.iter().map(|seat| seat.bet).max().unwrap_or(0);
+        self.actor.map_or(0, |actor| highest - bet(actor))
+    }
+
+    /// The state as the player in the given seat may see it.
+    pub fn snapshot(&self, player: usize) -> StateSnapshot {
+        StateSnapshot {
+            player,
+            hand: self.hand,
+            street: self.street,
+            board: self.board.clone(),
+            pot: self.pot,
+            seats: self.seats.clone(),
+            actor: self.actor,
+            min_raise: self.min_raise,
+            hole: self.holes.get(player).copied().flatten(),
+        }
+    }
+
+    /// Take over the state from a snapshot.
+    fn restore(&mut self, snapshot: &StateSnapshot) {
+        self.hand = snapshot.hand;
+        self.street = snapshot.street;
+        self.board = snapshot.board.clone();
+        self.pot = snapshot.pot;
+        self.seats = snapshot.seats.clone();
+        self.actor = snapshot.actor;
+        self.min_raise = snapshot.min_raise;
+        self.holes = vec![None; self.seats.len()];
+        if let Some(hole) = self.holes.get_mut(snapshot.player) {
+            *hole = snapshot.hole;
+        }
+    }
+
+    /// Whether the message is the first one of a new hand.
+    pub fn begins_hand(&self, message: &Message) -> bool {
+        self.street == Street::Finished
+            && matches!(
+                message,
+                Message::Hole { .. } | Message::PlayerActed { .. } | Message::RequestAction { .. }
+            )
+    }
+
+    /// Start a new hand with the given id, everybody with chips is dealt
+    /// in. Hands that are started by `apply` are numbered from 1.
+    pub fn start_hand(&mut self, id: usize) {
+        self.hand = id;
+        self.street = Street::PreFlop;
+        self.board.clear();
+        self.pot = 0;
+        self.actor = None;
+        self.min_raise = None;
+        self.holes.iter_mut().for_each(|hole| *hole = None);
+        for seat in &mut self.seats {
+            seat.bet = 0;
+            if seat.status != SeatStatus::Empty {
+                seat.status = match seat.stack {
+                    0 => SeatStatus::Out,
+                    _ => SeatStatus::Active,
+                };
+            }
+        }
+    }
+
+    /// Deal board cards, which moves the bets into the pot.
+    fn deal(&mut self, street: Street, cards: &[Card]) {
+        self.street = street;
+        self.board.extend_from_slice(cards);
+        self.pot += self.seats.iter().map(|seat| seat.bet).sum::<Money>();
+        self.seats.iter_mut().for_each(|seat| seat.bet = 0);
+        self.actor = None;
+        self.min_raise = None;
+    }
+
+    /// End the hand, with the stacks the seats are left with.
+    fn finish(&mut self, stacks: &[Money]) {
+        self.resize(stacks.len());
+        self.street = Street::Finished;
+        self.actor = None;
+        self.min_raise = None;
+        for (seat, &stack) in self.seats.iter_mut().zip(stacks) {
+            seat.stack = stack;
+            seat.bet = 0;
+        }
+    }
+
+    /// Make room for at least the given number of seats.
+    fn resize(&mut self, seats: usize) {
+        while self.seats.len() < seats {
+            self.seats.push(SeatState {
+                name: None,
+                stack: 0,
+                bet: 0,
+                status: SeatStatus::Out,
+            });
+            self.holes.push(None);
+            self.start.push(0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::communication::{
+        Ante, Callback, Error, GameType, PlayerAction, Response, Straddle, Suit,
+    };
+    use crate::engine::Table;
+
+    /// Follows the table and checks that what it asks and tells matches the
+    /// state. Raises the first time each player acts, and calls after.
+    struct Follower {
+        state: GameState,
+        raised: Vec<bool>,
+        requests: usize,
+    }
+
+    impl Callback for Follower {
+        fn callback(&mut self, message: Message) -> Result<Response, Error> {
+            let before = self.state.clone();
+            self.state.apply(&message);
+            match message {
+                Message::RequestAction {
+                    player,
+                    bets,
+                    pot,
+                    min_raise,
+                } => {
+                    self.requests += 1;
+                    let state = self.state.snapshot(player);
+                    assert_eq!(state.actor, Some(player));
+                    assert_eq!(state.min_raise, min_raise);
+                    assert_eq!(state.pot, pot);
+                    assert!(state.hole.is_some());
+                    for (seat, bet) in state.seats.iter().zip(bets) {
+                        match bet {
+                            Some(bet) => assert_eq!(seat.bet, bet),
+                            None => assert!(seat.status != SeatStatus::Active),
+                        }
+                    }
+                    if self.raised[player] {
+                        return Ok(Response::Action(PlayerAction::Call));
+                    }
+                    self.raised[player] = true;
+                    return Ok(Response::Action(PlayerAction::Raise(2)));
+                }
+                Message::Showdown { pot, .. } => {
+                    // everything that was bet ends up in the pot
+                    let bets = before.seats.iter().map(|seat| seat.bet).sum::<Money>();
+                    assert_eq!(before.pot + bets, pot);
+                    assert_eq!(before.board.len(), 5);
+                }
+                _ => {}
+            }
+            Ok(Response::Ack)
+        }
+    }
+
+    #[test]
+    fn test_follow_table() {
+        let stacks = vec![20, 100, 0, 100];
+        let mut table = Table::new(
+            GameType::NoLimit,
+            1,
+            2,
+            Ante::PerPlayer(1),
+            Straddle::None,
+            stacks.clone(),
+            Follower {
+                state: GameState::new(stacks),
+                raised: vec![false; 4],
+                requests: 0,
+            },
+        );
+        for hand in 1..=3 {
+            table.play_round().unwrap();
+            let follower = table.callback_mut();
+            assert!(follower.requests > 0);
+            assert_eq!(follower.state.hand, hand);
+            assert_eq!(follower.state.street, Street::Finished);
+            follower.raised = vec![false; 4];
+            follower.requests = 0;
+        }
+        let state = &table.callback_mut().state;
+        assert_eq!(state.seats[2].status, SeatStatus::Out);
+        let stacks = state
+            .seats
+            .iter()
+            .map(|seat| seat.stack)
+            .collect::<Vec<_>>();
+        assert_eq!(stacks, table.stacks());
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let card = |rank, suit| Card { rank, suit };
+        let hole = (card(14, Suit::Spades), card(13, Suit::Spades));
+        let mut state = GameState::new(vec![100, 100, 100]);
+        let messages = vec![
+            Message::Players(vec![
+                Some(String::from("alice")),
+                None,
+                Some(String::from("bob")),
+            ]),
+            Message::Hole {
+                player: 0,
+                cards: hole,
+            },
+            Message::Hole {
+                player: 2,
+                cards: (card(2, Suit::Hearts), card(7, Suit::Clubs)),
+            },
+            Message::PlayerActed {
+                player: 2,
+                action: Action::SmallBlind,
+                amount: 1,
+                stack: 99,
+            },
+            Message::PlayerActed {
+                player: 0,
+                action: Action::BigBlind,
+                amount: 2,
+                stack: 98,
+            },
+            Message::RequestAction {
+                player: 2,
+                bets: vec![Some(2), None, Some(1)],
+                pot: 0,
+                min_raise: Some(2),
+            },
+            Message::PlayerActed {
+                player: 2,
+                action: Action::Call,
+                amount: 1,
+                stack: 98,
+            },
+            Message::Flop(
+                card(2, Suit::Clubs),
+                card(3, Suit::Clubs),
+                card(9, Suit::Hearts),
+            ),
+            Message::RequestAction {
+                player: 2,
+                bets: vec![Some(0), None, Some(0)],
+                pot: 4,
+                min_raise: Some(2),
+            },
+        ];
+        for message in &messages {
+            state.apply(message);
+        }
+        let seat = |name: Option<&str>, stack, status| SeatState {
+            name: name.map(String::from),
+            stack,
+            bet: 0,
+            status,
+        };
+        let snapshot = state.snapshot(0);
+        assert_eq!(
+            snapshot,
+            StateSnapshot {
+                player: 0,
+                hand: 1,
+                street: Street::Flop,
+                board: vec![
+                    card(2, Suit::Clubs),
+                    card(3, Suit::Clubs),
+                    card(9, Suit::Hearts)
+                ],
+                pot: 4,
+                seats: vec![
+                    seat(Some("alice"), 98, SeatStatus::Active),
+                    seat(None, 100, SeatStatus::Empty),
+                    seat(Some("bob"), 98, SeatStatus::Active),
+                ],
+                actor: Some(2),
+                min_raise: Some(2),
+                hole: Some(hole),
+            }
+        );
+
+        // a client that missed everything catches up from the snapshot, and
+        // only learns its own hole cards
+        let mut client = GameState::new(Vec::new());
+        client.apply(&Message::StateSnapshot(snapshot.clone()));
+        assert_eq!(client.snapshot(0), snapshot);
+        assert_eq!(client.snapshot(2).hole, None);
+        client.apply(&Message::PlayerActed {
+            player: 2,
+            action: Action::Raise,
+            amount: 98,
+            stack: 0,
+        });
+        assert_eq!(client.seats[2].status, SeatStatus::AllIn);
+        assert_eq!(client.actor, None);
+    }
+}
@@ -0,0 +1,257 @@
+use serde::Serialize;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::communication::{Ranking, TableInfo, TableStatus};
+use crate::ledger::Ledger;
+use crate::lobby::{HandResult, Tables};
+
+/// Everything the status page shows.
+#[derive(Serialize)]
+struct Status {
+    tables: Vec<TableInfo>,
+    clients: Clients,
+    results: Vec<HandResult>,
+    leaderboard: Vec<Ranking>,
+}
+
+/// The connected clients by where they are.
+#[derive(Serialize)]
+struct Clients {
+    lobby: usize,
+    // waiting at a forming table
+    waiting: usize,
+    playing: usize,
+}
+
+impl Status {
+    fn new(tables: &Tables, ledger: &Ledger) -> Status {
+        let tables_info = tables.lobby().tables;
+        let seated = |status| {
+            tables_info
+                .iter()
+                .filter(|t| t.status == status)
+                .map(|t| t.seated)
+                .sum()
+        };
+        Status {
+            clients: Clients {
+                lobby: tables.lobby_clients(),
+                waiting: seated(TableStatus::Forming),
+                playing: seated(TableStatus::Running),
+            },
+            results: tables.results(),
+            tables: tables_info,
+            leaderboard: ledger.leaderboard().players,
+        }
+    }
+}
+
+// how long a client may take to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Serve the state of the server over HTTP until the listener fails. `/`
+/// is a page that refreshes itself every few seconds, `/status.json` gives
+/// the same as JSON. Every connection is handled on a thread of its own, so
+/// a slow client does not hold up the others.
+pub fn serve_status(listener: TcpListener, tables: Arc<Mutex<Tables>>, ledger: Arc<Mutex<Ledger>>) {
+    for stream in listener.incoming().filter_map(Result::ok) {
+        let tables = tables.clone();
+        let ledger = ledger.clone();
+        thread::spawn(move || {
+            stream.set_read_timeout(Some(REQUEST_TIMEOUT)).ok();
+            handle(stream, || {
+                Status::new(&tables.lock().unwrap(), &ledger.lock().unwrap())
+            })
+            .ok();
+        });
+    }
+}
+
+/// Answer a single request and close the connection. The status is taken
+/// once the request is read.
+fn handle<F>(mut stream: TcpStream, status: F) -> std::io::Result<()>
+where
+    F: FnOnce() -> Status,
+{
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // skip the headers, there is no body to a GET request
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let path = request_line.split_whitespace().nth(1).unwrap_or("/");
+    let (code, content_type, body) = response(path, &status());
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        code,
+        content_type,
+        body.len(),
+        body
+    )
+}
+
+/// Returns: the status code, content type and body for a path
+fn response(path: &str, status: &Status) -> (&'static str, &'static str, String) {
+    match path {
+        "/" | "/index.html" => ("200 OK", "text/html; charset=utf-8", html(status)),
+        "/status.json" => (
+            "200 OK",
+            "application/json",
+            serde_json::to_string(status).unwrap(),
+        ),
+        _ => ("404 Not Found", "text/plain", String::from("not found")),
+    }
+}
+
+fn html(status: &Status) -> String {
+    let mut page = String::from(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\">\
+         <meta http-equiv=\"refresh\" content=\"2\"><title>Pokerroom</title></head><body>\n",
+    );
+    page += &format!(
+        "<h1>Pokerroom</h1>\n<p>{} clients in the lobby, {} waiting for a table, {} playing</p>\n",
+        status.clients.lobby, status.clients.waiting, status.clients.playing
+    );
+
+    page += "<h2>Tables</h2>\n<table>\n<tr><th>id</th><th>status</th><th>game</th>\
+             <th>blinds</th><th>format</th><th>seats</th><th>average pot</th>\
+             <th>hands per hour</th></tr>\n";
+    for table in &status.tables {
+        let request = &table.request;
+        page += &format!(
+            "<tr><td>{}</td><td>{:?}</td><td>{:?}</td><td>{}/{}</td><td>{}</td>\
+             <td>{}/{}</td><td>{}</td><td>{}</td></tr>\n",
+            table.id,
+            table.status,
+            request.game_type,
+            request.small_blind,
+            request.big_blind,
+            serde_json::to_string(&request.format).unwrap(),
+            table.seated,
+            table.seats,
+            table.average_pot,
+            table.hands_per_hour
+        );
+    }
+    page += "</table>\n";
+
+    page += "<h2>Recent hands</h2>\n<table>\n<tr><th>table</th><th>hand</th><th>pot</th><th>winners</th></tr>\n";
+    for result in &status.results {
+        page += &format!(
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{:?}</td></tr>\n",
+            result.table, result.hand, result.pot, result.winners
+        );
+    }
+    page += "</table>\n";
+
+    page += "<h2>Leaderboard</h2>\n<table>\n<tr><th>player</th><th>rating</th><th>games</th>\
+             <th>hands</th><th>bb/100</th><th>bankroll</th></tr>\n";
+    for ranking in &status.leaderboard {
+        let interval = match ranking.interval {
+            Some(interval) => format!(" &plusmn; {:.1}", interval),
+            None => String::new(),
+        };
+        page += &format!(
+            "<tr><td>{}</td><td>{:.0}</td><td>{}</td><td>{}</td><td>{:.1}{}</td><td>{}</td></tr>\n",
+            escape(&ranking.name),
+            ranking.rating,
+            ranking.games,
+            ranking.hands,
+            ranking.winrate,
+            interval,
+            ranking.bankroll
+        );
+    }
+    page + "</table>\n</body></html>\n"
+}
+
+/// Make a player's name safe to put in a page.
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::communication::{Ante, Format, GameType, Straddle, TableRequest};
+    use crate::ledger::Record;
+    use crate::lobby::TableHandle;
+    use std::io::Read;
+
+    #[test]
+    fn test_status() {
+        let request = TableRequest {
+            n_players: 2,
+            small_blind: 1,
+            big_blind: 2,
+            stack: 100,
+            game_type: GameType::NoLimit,
+            ante: Ante::None,
+            straddle: Straddle::None,
+            format: Format::Cash,
+        };
+        let tables = Arc::new(Mutex::new(Tables::default()));
+        let forming = tables
+            .lock()
+            .unwrap()
+            .open(request.clone(), TableStatus::Forming);
+        tables.lock().unwrap().set_seated(forming, 1);
+        let running = tables.lock().unwrap().open(request, TableStatus::Running);
+        let handle = TableHandle {
+            id: running,
+            tables: tables.clone(),
+        };
+        handle.joined();
+        handle.joined();
+        handle.hand_played(1, 4, vec![1]);
+        tables.lock().unwrap().set_lobby_clients(3);
+
+        let mut ledger = Ledger::in_memory();
+        ledger.record(Record::Game {
+            table: running,
+            standings: vec![String::from("<b>"), String::from("bob")],
+        });
+
+        let status = Status::new(&tables.lock().unwrap(), &ledger);
+        let (code, _, body) = response("/status.json", &status);
+        assert_eq!(code, "200 OK");
+        let json: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(json["clients"]["lobby"], 3);
+        assert_eq!(json["clients"]["waiting"], 1);
+        assert_eq!(json["clients"]["playing"], 2);
+        assert_eq!(json["tables"].as_array().unwrap().len(), 2);
+        assert_eq!(json["results"][0]["pot"], 4);
+        assert_eq!(json["leaderboard"][0]["name"], "<b>");
+
+        let (code, content_type, body) = response("/", &status);
+        assert_eq!(code, "200 OK");
+        assert!(content_type.starts_with("text/html"));
+        assert!(body.contains("<td>1/2</td>"));
+        assert!(body.contains("<td>&lt;b&gt;</td>"));
+
+        assert_eq!(response("/nope", &status).0, "404 Not Found");
+
+        // a client that never sends its request does not hold up the others
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let ledger = Arc::new(Mutex::new(ledger));
+        thread::spawn(move || serve_status(listener, tables, ledger));
+        let _idle = TcpStream::connect(address).unwrap();
+        let mut client = TcpStream::connect(address).unwrap();
+        write!(client, "GET /status.json HTTP/1.1\r\n\r\n").unwrap();
+        let mut reply = String::new();
+        client.read_to_string(&mut reply).unwrap();
+        assert!(reply.starts_with("HTTP/1.1 200 OK"), "{}", reply);
+    }
+}
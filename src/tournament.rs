@@ -0,0 +1,458 @@
+use std::thread;
+use std::time::Instant;
+
+use crate::communication::{BlindSchedule, Message, Seating, TableRequest};
+use crate::engine::{blind_level, Table};
+
+/// A tournament spread over several tables. All tables play a round at the
+/// same time (hand-for-hand), so players that bust in the same round can be
+/// ranked by the stack they started it with. Between rounds players are moved
+/// to keep the tables balanced, and tables that are no longer needed are
+/// broken up until the final table remains.
+pub struct MultiTable<T>
+where
+    T: Seating,
+{
+    schedule: BlindSchedule,
+    tables: Vec<Table<T>>,
+    // the entrant in every seat of every table
+    entrants: Vec<Vec<Option<usize>>>,
+}
+
+impl<T> MultiTable<T>
+where
+    T: Seating + Send,
+{
+    /// Create a table with `table_size` seats for every callback and spread
+    /// the entrants over them. Entrants are numbered in the order they are
+    /// given.
+    pub fn new(
+        table_request: &TableRequest,
+        schedule: &BlindSchedule,
+        table_size: usize,
+        callbacks: Vec<T>,
+        connections: Vec<T::Connection>,
+    ) -> MultiTable<T> {
+        let n_tables = callbacks.len();
+        let mut tables = callbacks
+            .into_iter()
+            .map(|callback| {
+                Table::new(
+                    table_request.game_type,
+                    table_request.small_blind,
+                    table_request.big_blind,
+                    table_request.ante,
+                    table_request.straddle,
+                    vec![0; table_size],
+                    callback,
+                )
+            })
+            .collect::<Vec<_>>();
+        let mut entrants = vec![vec![None; table_size]; n_tables];
+
+        for (entrant, connection) in connections.into_iter().enumerate() {
+            let (table, seat) = (entrant % n_tables, entrant / n_tables);
+            tables[table].set_stack(seat, table_request.stack);
+            tables[table]
+                .callback_mut()
+                .join(seat, connection, table_request.stack);
+            tables[table]
+                .callback_mut()
+                .callback(Message::Seated {
+                    table,
+                    player: seat,
+                    entrant,
+                })
+                .ok();
+            entrants[table][seat] = Some(entrant);
+        }
+
+        MultiTable {
+            schedule: schedule.clone(),
+            tables,
+            entrants,
+        }
+    }
+
+    /// Play until one entrant has all the chips. Busted players are handed
+    /// to `release` right away, the others once the tournament is over.
+    /// Players that make an illegal move are kicked: the round at their table
+    /// is undone, they finish below everyone busting in the same round and
+    /// their connection is not released but handed to `Seating::kick`.
+    /// When the callbacks ask to stop, the tournament ends unfinished. When a
+    /// round panics the tournament is aborted, and the players that are still
+    /// in it are left in their seats.
+    /// Returns: the entrants in the order they finished, the winner first,
+    /// or None if the tournament was stopped or aborted
+    pub fn play<F>(mut self, mut release: F) -> Option<Vec<usize>>
+    where
+        F: FnMut(T::Connection),
+    {
+        let start = Instant::now();
+        let mut hands = 0;
+        let mut level = None;
+        // entrants in the order in which they busted
+        let mut busted = Vec::new();
+
+        let mut stopped = false;
+        while self.remaining() > 1 {
+            for table in &mut self.tables {
+                stopped |= table.stopped();
+            }
+            if stopped {
+                break;
+            }
+            let next_level = blind_level(&self.schedule, hands, start);
+            if let Some(&blinds) = self.schedule.levels.get(next_level) {
+                if level.is_none_or(|level| next_level > level) {
+                    level = Some(next_level);
+                    for table in &mut self.tables {
+                        table.raise_blinds(next_level, blinds).ok();
+                    }
+                }
+            }
+
+            let stacks = self.tables.iter().map(|t| t.stacks()).collect::<Vec<_>>();
+            let results = thread::scope(|scope| {
+                self.tables
+                    .iter_mut()
+                    .map(|table| {
+                        scope.spawn(move || {
+                            if table.stacks().iter().filter(|&&s| s > 0).count() > 1 {
+                                table.play_round_isolated()
+                            } else {
+                                Some(Ok(()))
+                            }
+                        })
+                    })
+                    .collect::<Vec<_>>()
+                    .into_iter()
+                    .map(|handle| handle.join().expect("table thread panicked"))
+                    .collect::<Vec<_>>()
+            });
+            hands += 1;
+
+            // the tables that did not panic are aborted as well
+            if results.iter().any(Option::is_none) {
+                for (t, table) in self.tables.iter_mut().enumerate() {
+                    if results[t].is_some() {
+                        table.abort();
+                    }
+                }
+                return None;
+            }
+
+            let mut kicked = Vec::new();
+            for (t, result) in results.into_iter().flatten().enumerate() {
+                if let Err(e) = result {
+                    log!(
+                        "player {:?} at table {} messed up: {:?}",
+                        e.player,
+                        t,
+                        e.error
+                    );
+                    for (seat, &stack) in stacks[t].iter().enumerate() {
+                        self.tables[t].set_stack(seat, stack);
+                    }
+                    self.tables[t].set_stack(e.player, 0);
+                    kicked.push((t, e.player));
+                }
+            }
+
+            let remaining = stacks.iter().flatten().filter(|&&s| s > 0).count();
+            let mut bust_now = Vec::new();
+            for (t, table) in self.tables.iter().enumerate() {
+                for (seat, stack) in table.stacks().into_iter().enumerate() {
+                    if stacks[t][seat] > 0 && stack == 0 {
+                        let rank = if kicked.contains(&(t, seat)) {
+                            0
+                        } else {
+                            stacks[t][seat]
+                        };
+                        bust_now.push((rank, t, seat));
+                    }
+                }
+            }
+            bust_now.sort();
+            for (i, &(_, t, seat)) in bust_now.iter().enumerate() {
+                let entrant = self.entrants[t][seat]
+                    .take()
+                    .expect("busted seat has no entrant");
+                self.broadcast(|| Message::Eliminated {
+                    player: entrant,
+                    place: remaining - i,
+                });
+                let callback = self.tables[t].callback_mut();
+                match callback.leave(seat) {
+                    Some(connection) if kicked.contains(&(t, seat)) => callback.kick(connection),
+                    Some(connection) => release(connection),
+                    None => {}
+                }
+                busted.push(entrant);
+            }
+
+            let players = self
+                .entrants
+                .iter()
+                .map(|seats| seats.iter().flatten().count())
+                .collect::<Vec<_>>();
+            for (from, to) in balance(&players, self.entrants[0].len()) {
+                self.move_player(from, to);
+            }
+        }
+
+        let mut standings = self
+            .entrants
+            .iter()
+            .flatten()
+            .flatten()
+            .copied()
+            .collect::<Vec<_>>();
+        standings.extend(busted.iter().rev());
+        if !stopped {
+            self.broadcast(|| Message::Standings(standings.clone()));
+        }
+        for (t, table) in self.tables.iter_mut().enumerate() {
+            for seat in 0..self.entrants[t].len() {
+                if let Some(connection) = table.callback_mut().leave(seat) {
+                    release(connection);
+                }
+            }
+        }
+        if stopped {
+            None
+        } else {
+            Some(standings)
+        }
+    }
+
+    /// The number of entrants that are still in the tournament.
+    fn remaining(&self) -> usize {
+        self.entrants.iter().flatten().flatten().count()
+    }
+
+    /// Send a message to the players at every table.
+    fn broadcast<F>(&mut self, message: F)
+    where
+        F: Fn() -> Message,
+    {
+        for table in &mut self.tables {
+            table.callback_mut().callback(message()).ok();
+        }
+    }
+
+    /// Move the player that is due to post the big blind to an empty seat at
+    /// another table, which is the fairest player to move.
+    fn move_player(&mut self, from: usize, to: usize) {
+        let seat = self.tables[from].next_big_blind();
+        let stack = self.tables[from].stacks()[seat];
+        let entrant = self.entrants[from][seat]
+            .take()
+            .expect("moved seat has no entrant");
+        let connection = self.tables[from].callback_mut().leave(seat);
+        self.tables[from].set_stack(seat, 0);
+
+        let new_seat = self.entrants[to]
+            .iter()
+            .position(Option::is_none)
+            .expect("table to move to is full");
+        self.entrants[to][new_seat] = Some(entrant);
+        self.tables[to].set_stack(new_seat, stack);
+        if let Some(connection) = connection {
+            self.tables[to]
+                .callback_mut()
+                .join(new_seat, connection, stack);
+        }
+        self.tables[to]
+            .callback_mut()
+            .callback(Message::Seated {
+                table: to,
+                player: new_seat,
+                entrant,
+            })
+            .ok();
+    }
+}
+
+/// Decide which players to move so that no more tables are used than needed
+/// and the number of players per table differs by at most one. Tables without
+/// players are broken and stay empty.
+/// Returns: the table to take each player from and the table to seat them at
+fn balance(players: &[usize], table_size: usize) -> Vec<(usize, usize)> {
+    let mut players = players.to_vec();
+    let mut moves = Vec::new();
+    let total = players.iter().sum::<usize>();
+    let needed = total.div_ceil(table_size).max(1);
+    let open = |players: &[usize]| {
+        (0..players.len())
+            .filter(|&t| players[t] > 0)
+            .collect::<Vec<_>>()
+    };
+
+    // break the smallest tables until just enough are left
+    while open(&players).len() > needed {
+        let from = *open(&players).iter().min_by_key(|&&t| players[t]).unwrap();
+        while players[from] > 0 {
+            let to = *open(&players)
+                .iter()
+                .filter(|&&t| t != from && players[t] < table_size)
+                .min_by_key(|&&t| players[t])
+                .expect("not enough seats to break a table");
+            moves.push((from, to));
+            players[from] -= 1;
+            players[to] += 1;
+        }
+    }
+
+    // move players from the biggest to the smallest tables
+    loop {
+        let tables = open(&players);
+        match (
+            tables.iter().max_by_key(|&&t| players[t]),
+            tables.iter().min_by_key(|&&t| players[t]),
+        ) {
+            (Some(&from), Some(&to)) if players[from] > players[to] + 1 => {
+                moves.push((from, to));
+                players[from] -= 1;
+                players[to] += 1;
+            }
+            _ => return moves,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::communication::{
+        Ante, BlindLevel, Callback, Error, Format, GameType, LevelUp, Money, PlayerAction,
+        Response, Straddle,
+    };
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_balance() {
+        // (players per table, table size, tables after balancing)
+        let cases = vec![
+            (vec![6, 6, 6], 6, vec![6, 6, 6]),
+            (vec![6, 4, 6], 6, vec![6, 5, 5]),
+            (vec![6, 3, 5], 6, vec![5, 4, 5]),
+            (vec![6, 2, 6], 6, vec![5, 4, 5]),
+            (vec![4, 4, 4], 6, vec![0, 6, 6]),
+            (vec![3, 2, 0], 6, vec![5, 0, 0]),
+            (vec![1, 1], 6, vec![0, 2]),
+            (vec![0, 6, 1], 6, vec![0, 4, 3]),
+            (vec![1, 0], 6, vec![1, 0]),
+        ];
+
+        for (players, table_size, expected) in cases {
+            let mut balanced = players.clone();
+            for (from, to) in balance(&players, table_size) {
+                balanced[from] -= 1;
+                balanced[to] += 1;
+            }
+            assert_eq!(balanced, expected, "{:?}", players);
+        }
+    }
+
+    /// Calls every bet, except for the cheater that raises more than anyone
+    /// can. The connection is the entrant number.
+    struct Seat {
+        seats: Vec<Option<usize>>,
+        cheater: usize,
+        // the entrants sent away from every table
+        kicked: Arc<Mutex<Vec<usize>>>,
+    }
+
+    impl Callback for Seat {
+        fn callback(&mut self, message: Message) -> Result<Response, Error> {
+            match message {
+                Message::RequestAction { player, .. } => match self.seats[player] {
+                    Some(entrant) if entrant == self.cheater => {
+                        Ok(Response::Action(PlayerAction::Raise(Money::MAX)))
+                    }
+                    Some(_) => Ok(Response::Action(PlayerAction::Call)),
+                    None => panic!("asked an empty seat to act"),
+                },
+                Message::Seated {
+                    player, entrant, ..
+                } => {
+                    assert_eq!(self.seats[player], Some(entrant));
+                    Ok(Response::Ack)
+                }
+                _ => Ok(Response::Ack),
+            }
+        }
+    }
+
+    impl Seating for Seat {
+        type Connection = usize;
+
+        fn leave(&mut self, seat: usize) -> Option<usize> {
+            self.seats[seat].take()
+        }
+
+        fn join(&mut self, seat: usize, connection: usize, _stack: Money) {
+            assert!(self.seats[seat].is_none(), "seated on an occupied seat");
+            self.seats[seat] = Some(connection);
+        }
+
+        fn kick(&mut self, connection: usize) {
+            self.kicked.lock().unwrap().push(connection);
+        }
+    }
+
+    #[test]
+    fn test_multi_table() {
+        let blinds = |small_blind, big_blind| BlindLevel {
+            small_blind,
+            big_blind,
+            ante: Ante::None,
+        };
+        let schedule = BlindSchedule {
+            levels: vec![
+                blinds(1, 2),
+                blinds(5, 10),
+                blinds(25, 50),
+                blinds(100, 200),
+            ],
+            level_up: LevelUp::Hands(2),
+        };
+        let request = TableRequest {
+            n_players: 14,
+            small_blind: 1,
+            big_blind: 2,
+            stack: 100,
+            game_type: GameType::NoLimit,
+            ante: Ante::None,
+            straddle: Straddle::None,
+            format: Format::MultiTable {
+                schedule: schedule.clone(),
+                table_size: 6,
+            },
+        };
+        let kicked = Arc::new(Mutex::new(Vec::new()));
+        let callbacks = (0..3)
+            .map(|_| Seat {
+                seats: vec![None; 6],
+                cheater: 3,
+                kicked: kicked.clone(),
+            })
+            .collect();
+
+        let mut released = Vec::new();
+        let standings = MultiTable::new(&request, &schedule, 6, callbacks, (0..14).collect())
+            .play(|entrant| released.push(entrant))
+            .unwrap();
+
+        let mut entrants = standings.clone();
+        entrants.sort();
+        assert_eq!(entrants, (0..14).collect::<Vec<_>>());
+        // the cheater is kicked in the first round and finishes last, without
+        // being released
+        assert_eq!(standings.last(), Some(&3));
+        assert_eq!(*kicked.lock().unwrap(), vec![3]);
+        released.reverse();
+        assert_eq!(released, standings[..13]);
+    }
+}
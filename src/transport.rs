@@ -0,0 +1,320 @@
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, TryRecvError};
+use std::thread;
+use std::time::Duration;
+use websocket::result::WebSocketError;
+use websocket::sync::stream::AsTcpStream;
+use websocket::sync::{Client, Stream};
+use websocket::{Message as WsMessage, OwnedMessage};
+
+use crate::protocol::Frame;
+
+// the longest line a client may send, in bytes, longer lines close the
+// connection
+const MAX_LINE: usize = 64 * 1024;
+
+/// A connection to a client, whatever it is made of.
+pub(crate) trait Transport: Send {
+    /// Send a single message.
+    fn send(&mut self, frame: Frame) -> io::Result<()>;
+
+    /// Wait for the next message, for at most the read timeout. Fails with
+    /// `WouldBlock` or `TimedOut` when there is none yet, and with another
+    /// error when the connection is gone.
+    fn recv(&mut self) -> io::Result<Frame>;
+
+    /// Make `recv` return right away when there is no message.
+    fn set_nonblocking(&mut self, nonblocking: bool) -> io::Result<()>;
+
+    /// How long `recv` waits for a message, None waits forever.
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()>;
+
+    /// Whether binary frames can be sent.
+    fn binary(&self) -> bool;
+
+    /// Say goodbye to the client. The connection is closed once the
+    /// transport is dropped.
+    fn close(&mut self);
+}
+
+impl<S> Transport for Client<S>
+where
+    S: AsTcpStream + Stream + Send,
+{
+    fn send(&mut self, frame: Frame) -> io::Result<()> {
+        let message = match frame {
+            Frame::Text(text) => WsMessage::text(text),
+            Frame::Binary(bytes) => WsMessage::binary(bytes),
+        };
+        self.send_message(&message).map_err(io_error)
+    }
+
+    fn recv(&mut self) -> io::Result<Frame> {
+        loop {
+            match self.recv_message().map_err(io_error)? {
+                OwnedMessage::Text(text) => return Ok(Frame::Text(text)),
+                OwnedMessage::Binary(bytes) => return Ok(Frame::Binary(bytes)),
+                OwnedMessage::Close(_) => return Err(ErrorKind::ConnectionAborted.into()),
+                // pings and pongs
+                _ => {}
+            }
+        }
+    }
+
+    fn set_nonblocking(&mut self, nonblocking: bool) -> io::Result<()> {
+        Client::set_nonblocking(self, nonblocking)
+    }
+
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        self.stream_ref().as_tcp().set_read_timeout(timeout)
+    }
+
+    fn binary(&self) -> bool {
+        true
+    }
+
+    fn close(&mut self) {
+        self.send_message(&WsMessage::close()).ok();
+    }
+}
+
+fn io_error(error: WebSocketError) -> io::Error {
+    match error {
+        WebSocketError::IoError(error) => error,
+        error => io::Error::new(ErrorKind::InvalidData, error),
+    }
+}
+
+/// A connection that carries a JSON message per line: a plain TCP
+/// connection, or the standard input and output of a bot the server runs
+/// itself. Lines are read by a thread of their own, so reads can time out.
+pub(crate) struct Lines {
+    writer: Box<dyn Write + Send>,
+    lines: Receiver<io::Result<String>>,
+    nonblocking: bool,
+    timeout: Option<Duration>,
+    // ends the connection, which stops the reading thread as well
+    closer: Option<Box<dyn FnOnce() + Send>>,
+}
+
+impl Lines {
+    fn new<R>(reader: R, writer: Box<dyn Write + Send>, closer: Box<dyn FnOnce() + Send>) -> Lines
+    where
+        R: Read + Send + 'static,
+    {
+        let (tx, lines) = channel();
+        thread::spawn(move || {
+            let mut reader = BufReader::new(reader);
+            let mut line = Vec::new();
+            loop {
+                line.clear();
+                // read one byte more than a line may have to tell when it is
+                // too long
+                let limit = MAX_LINE as u64 + 1;
+                match (&mut reader).take(limit).read_until(b'\n', &mut line) {
+                    Ok(0) | Err(_) => return,
+                    Ok(_) => {}
+                }
+                if line.strip_suffix(b"\n").unwrap_or(&line).len() > MAX_LINE {
+                    let error = io::Error::new(ErrorKind::InvalidData, "line too long");
+                    tx.send(Err(error)).ok();
+                    return;
+                }
+                let line = match std::str::from_utf8(&line) {
+                    Ok(line) => line.trim_end(),
+                    Err(_) => return,
+                };
+                if !line.trim().is_empty() && tx.send(Ok(line.to_string())).is_err() {
+                    return;
+                }
+            }
+        });
+        Lines {
+            writer,
+            lines,
+            nonblocking: false,
+            timeout: None,
+            closer: Some(closer),
+        }
+    }
+
+    /// Talk line-delimited JSON over a plain TCP connection.
+    pub(crate) fn tcp(stream: TcpStream) -> io::Result<Lines> {
+        let reader = stream.try_clone()?;
+        let closing = stream.try_clone()?;
+        Ok(Lines::new(
+            reader,
+            Box::new(stream),
+            Box::new(move || {
+                closing.shutdown(Shutdown::Both).ok();
+            }),
+        ))
+    }
+
+    /// Run a bot and talk to it over its standard input and output. The bot
+    /// is killed when the connection closes.
+    /// command: the program and its arguments
+    pub(crate) fn spawn(command: &[String]) -> io::Result<Lines> {
+        let (program, args) = command
+            .split_first()
+            .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "no command to run"))?;
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+        Ok(Lines::new(
+            stdout,
+            Box::new(stdin),
+            Box::new(move || {
+                child.kill().ok();
+                child.wait().ok();
+            }),
+        ))
+    }
+}
+
+impl Transport for Lines {
+    fn send(&mut self, frame: Frame) -> io::Result<()> {
+        match frame {
+            Frame::Text(text) => {
+                writeln!(self.writer, "{}", text)?;
+                self.writer.flush()
+            }
+            Frame::Binary(_) => Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "binary messages do not fit on a line",
+            )),
+        }
+    }
+
+    fn recv(&mut self) -> io::Result<Frame> {
+        let line = if self.nonblocking {
+            self.lines.try_recv().map_err(|e| match e {
+                TryRecvError::Empty => ErrorKind::WouldBlock,
+                TryRecvError::Disconnected => ErrorKind::ConnectionAborted,
+            })
+        } else if let Some(timeout) = self.timeout {
+            self.lines.recv_timeout(timeout).map_err(|e| match e {
+                RecvTimeoutError::Timeout => ErrorKind::TimedOut,
+                RecvTimeoutError::Disconnected => ErrorKind::ConnectionAborted,
+            })
+        } else {
+            self.lines.recv().map_err(|_| ErrorKind::ConnectionAborted)
+        };
+        match line? {
+            Ok(line) => Ok(Frame::Text(line)),
+            Err(error) => {
+                self.close();
+                Err(error)
+            }
+        }
+    }
+
+    fn set_nonblocking(&mut self, nonblocking: bool) -> io::Result<()> {
+        self.nonblocking = nonblocking;
+        Ok(())
+    }
+
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        self.timeout = timeout;
+        Ok(())
+    }
+
+    fn binary(&self) -> bool {
+        false
+    }
+
+    fn close(&mut self) {
+        if let Some(closer) = self.closer.take() {
+            closer();
+        }
+    }
+}
+
+impl Drop for Lines {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let mut lines = Lines::tcp(listener.accept().unwrap().0).unwrap();
+
+        lines.send(Frame::Text(String::from("\"Ack\""))).unwrap();
+        let mut reader = BufReader::new(client.try_clone().unwrap());
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(line, "\"Ack\"\n");
+        assert!(lines.send(Frame::Binary(vec![1])).is_err());
+
+        // blank lines are skipped, and reads do not wait when asked not to
+        write!(client, "\n{{\"Action\":\"Call\"}}\n").unwrap();
+        let frame = lines.recv().unwrap();
+        assert_eq!(frame, Frame::Text(String::from("{\"Action\":\"Call\"}")));
+        lines.set_nonblocking(true).unwrap();
+        assert_eq!(lines.recv().unwrap_err().kind(), ErrorKind::WouldBlock);
+        lines.set_nonblocking(false).unwrap();
+        lines
+            .set_read_timeout(Some(Duration::from_millis(10)))
+            .unwrap();
+        assert_eq!(lines.recv().unwrap_err().kind(), ErrorKind::TimedOut);
+
+        // the client hangs up
+        drop(reader);
+        client.shutdown(Shutdown::Both).unwrap();
+        lines.set_read_timeout(None).unwrap();
+        assert_eq!(
+            lines.recv().unwrap_err().kind(),
+            ErrorKind::ConnectionAborted
+        );
+    }
+
+    #[test]
+    fn test_long_line() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let mut lines = Lines::tcp(listener.accept().unwrap().0).unwrap();
+
+        // a line as long as allowed goes through
+        let longest = format!("\"{}\"", "a".repeat(MAX_LINE - 2));
+        writeln!(client, "{}", longest).unwrap();
+        assert_eq!(lines.recv().unwrap(), Frame::Text(longest));
+
+        // a longer one ends the connection, without waiting for its end
+        client.write_all(&[b'a'; MAX_LINE + 1]).unwrap();
+        assert_eq!(lines.recv().unwrap_err().kind(), ErrorKind::InvalidData);
+        let mut rest = Vec::new();
+        client.read_to_end(&mut rest).unwrap();
+        assert!(rest.is_empty());
+        assert!(lines.recv().is_err());
+    }
+
+    #[test]
+    fn test_spawn() {
+        // a bot that repeats everything it is told
+        let mut bot = Lines::spawn(&[String::from("cat")]).unwrap();
+        bot.send(Frame::Text(String::from("\"GameOver\""))).unwrap();
+        assert_eq!(
+            bot.recv().unwrap(),
+            Frame::Text(String::from("\"GameOver\""))
+        );
+        bot.close();
+        assert!(bot.recv().is_err());
+
+        assert!(Lines::spawn(&[]).is_err());
+        assert!(Lines::spawn(&[String::from("/no/such/bot")]).is_err());
+    }
+}